@@ -0,0 +1,91 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use gdb_protocol::{
+    commands::to_hex,
+    packet::{CheckedPacket, Kind, UncheckedPacket},
+    parser::Parser,
+};
+
+/// Encodes a memory read reply of `len` bytes, which is hex and so
+/// needs no escaping.
+fn memory_reply(len: usize) -> Vec<u8> {
+    let data: Vec<u8> = (0..len).map(|i| i as u8).collect();
+    let mut encoded = Vec::new();
+    CheckedPacket::from_data(Kind::Packet, to_hex(&data))
+        .encode(&mut encoded)
+        .unwrap();
+    encoded
+}
+
+fn large_packets(c: &mut Criterion) {
+    let mut group = c.benchmark_group("large packets");
+    for &len in &[1024, 64 * 1024, 1024 * 1024] {
+        let input = memory_reply(len);
+        group.throughput(Throughput::Bytes(input.len() as u64));
+        group.bench_with_input(BenchmarkId::new("whole", len), &input, |b, input| {
+            b.iter(|| Parser::default().feed_checked(black_box(input)).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("chunked", len), &input, |b, input| {
+            b.iter(|| {
+                let mut parser = Parser::default();
+                let mut packet = None;
+                for chunk in black_box(input).chunks(8 * 1024) {
+                    packet = parser.feed_checked(chunk).unwrap().1.or(packet);
+                }
+                packet.unwrap()
+            })
+        });
+        // The baseline the parser should get close to
+        group.bench_with_input(BenchmarkId::new("memcpy", len), &input, |b, input| {
+            b.iter(|| black_box(input).to_vec())
+        });
+    }
+    group.finish();
+}
+
+fn escaped_packets(c: &mut Criterion) {
+    let data: Vec<u8> = (0..64 * 1024).map(|i| i as u8).collect();
+    let mut input = Vec::new();
+    CheckedPacket::from_data(Kind::Packet, data)
+        .encode(&mut input)
+        .unwrap();
+
+    let mut group = c.benchmark_group("escaped packets");
+    group.throughput(Throughput::Bytes(input.len() as u64));
+    group.bench_function("binary", |b| {
+        b.iter(|| Parser::default().feed_checked(black_box(&input)).unwrap())
+    });
+    group.finish();
+}
+
+fn checksums(c: &mut Criterion) {
+    let packets: Vec<UncheckedPacket> = [*b"00", *b"9a", *b"FF", *b"c5"]
+        .iter()
+        .map(|&checksum| UncheckedPacket {
+            kind: Kind::Packet,
+            data: b"OK".to_vec(),
+            checksum,
+        })
+        .collect();
+
+    let mut group = c.benchmark_group("checksums");
+    group.bench_function("expected_checksum", |b| {
+        b.iter(|| {
+            for packet in black_box(&packets) {
+                black_box(packet.expected_checksum().unwrap());
+            }
+        })
+    });
+    // The path expected_checksum takes for anything but hex digits
+    group.bench_function("from_str_radix", |b| {
+        b.iter(|| {
+            for packet in black_box(&packets) {
+                let hex = std::str::from_utf8(&packet.checksum).unwrap();
+                black_box(u8::from_str_radix(hex, 16).unwrap());
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, large_packets, escaped_packets, checksums);
+criterion_main!(benches);
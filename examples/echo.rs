@@ -0,0 +1,54 @@
+//! A minimal stub, which prints every packet it receives and replies
+//! just enough for GDB to connect and detach. The server listens on an
+//! ephemeral port, unless one is specified as the first argument, and
+//! prints the port it listens on.
+
+use gdb_protocol::{
+    commands::{self, Command, ReadRegisters, StopReply},
+    io::GdbServer,
+    packet::{CheckedPacket, Kind},
+    Error,
+};
+use std::{env, io::prelude::*, net::TcpListener};
+
+fn main() -> Result<(), Error> {
+    let port = env::args().nth(1).unwrap_or_else(|| "0".to_string());
+    let listener = TcpListener::bind(format!("127.0.0.1:{}", port))?;
+    println!("Listening on port {}", listener.local_addr()?.port());
+    std::io::stdout().flush()?;
+
+    let (stream, _addr) = listener.accept()?;
+    let mut server = GdbServer::from_stream(stream)?;
+
+    while let Some(packet) = server.next_packet()? {
+        println!("-> {}", packet.data_lossy());
+
+        let reply = match commands::parse(&packet)? {
+            Some(Command::HaltReason) => StopReply::signal(5),
+            Some(Command::QueryAttached { .. }) => commands::attached_reply(true),
+            Some(Command::Detach { .. }) => {
+                server.dispatch(&commands::ok_reply())?;
+                break;
+            }
+            _ if packet.data.starts_with(b"qSupported") => {
+                CheckedPacket::from_data(Kind::Packet, b"PacketSize=1000".to_vec())
+            }
+            _ if packet.data.starts_with(b"H") => commands::ok_reply(),
+            // Registers read as zero, and memory can't be read
+            _ if ReadRegisters::from_packet(&packet).is_ok() => {
+                CheckedPacket::from_data(Kind::Packet, commands::to_hex(&[0; 8]))
+            }
+            _ if packet.data.starts_with(b"m") => commands::error_reply(1),
+            _ if packet.data == b"k" => break,
+            _ => {
+                server.reply_unsupported()?;
+                continue;
+            }
+        };
+        println!("<- {}", reply.data_lossy());
+        server.dispatch(&reply)?;
+    }
+
+    println!("Disconnected");
+    Ok(())
+}
@@ -7,11 +7,7 @@ fn main() -> Result<(), Error> {
     println!("Connected!");
 
     while let Some(packet) = server.next_packet()? {
-        println!(
-            "-> {:?} {:?}",
-            packet.kind,
-            std::str::from_utf8(&packet.data)
-        );
+        println!("-> {}", packet.display());
 
         print!(": ");
         io::stdout().flush()?;
@@ -22,9 +18,7 @@ fn main() -> Result<(), Error> {
         }
         let response = CheckedPacket::from_data(Kind::Packet, response.into_bytes());
 
-        let mut bytes = Vec::new();
-        response.encode(&mut bytes).unwrap();
-        println!("<- {:?}", std::str::from_utf8(&bytes));
+        println!("<- {}", response.display());
 
         server.dispatch(&response)?;
     }
@@ -0,0 +1,189 @@
+//! An asynchronous client on top of tokio, enabled by the `tokio`
+//! feature. The parser is the same as for the blocking `io` module,
+//! only the I/O is async.
+
+use crate::{
+    packet::{CheckedPacket, Kind},
+    parser::Parser,
+    Error,
+};
+
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream, ToSocketAddrs,
+    },
+};
+
+/// The host side of a connection, like `io::GdbClient`, but async.
+pub struct AsyncGdbClient<R, W>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    pub reader: R,
+    pub writer: W,
+    parser: Parser,
+}
+
+impl AsyncGdbClient<BufReader<OwnedReadHalf>, OwnedWriteHalf> {
+    /// Connects to a stub, disabling Nagle's algorithm as the protocol
+    /// consists of many small packets.
+    pub async fn connect<A>(addr: A) -> Result<Self, Error>
+    where
+        A: ToSocketAddrs,
+    {
+        let stream = TcpStream::connect(addr).await?;
+        stream.set_nodelay(true)?;
+        let (reader, writer) = stream.into_split();
+        Ok(Self::new(
+            BufReader::with_capacity(crate::io::BUF_SIZE, reader),
+            writer,
+        ))
+    }
+}
+impl<R, W> AsyncGdbClient<R, W>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    pub fn new(reader: R, writer: W) -> Self {
+        Self {
+            reader,
+            writer,
+            parser: Parser::default(),
+        }
+    }
+
+    /// Receives the next packet from the stub, acknowledging it, see
+    /// `io::GdbServer::next_packet`. Corrupted packets are denied with
+    /// `-`, so the stub retransmits them.
+    pub async fn recv_packet(&mut self) -> Result<Option<CheckedPacket>, Error> {
+        loop {
+            let buf = self.reader.fill_buf().await?;
+            if buf.is_empty() {
+                self.parser.finish()?;
+                break Ok(None);
+            }
+
+            let (read, packet) = self.parser.feed_checked(buf)?;
+            self.reader.consume(read);
+
+            match packet {
+                Some(Ok(packet)) => {
+                    if packet.kind == Kind::Packet {
+                        self.writer.write_all(b"+").await?;
+                        self.writer.flush().await?;
+                    }
+                    break Ok(Some(packet));
+                }
+                Some(Err(packet)) => match packet.kind {
+                    Kind::Packet => {
+                        self.writer.write_all(b"-").await?;
+                        self.writer.flush().await?;
+                    }
+                    // Protocol specifies notifications should not be checked
+                    Kind::Notification => break Ok(None),
+                },
+                None => (),
+            }
+        }
+    }
+    /// Sends a packet to the stub, retrying upon any failed checksum
+    /// verification on the remote, see `io::GdbServer::dispatch`.
+    pub async fn send_packet(&mut self, packet: &CheckedPacket) -> Result<(), Error> {
+        let mut encoded = Vec::with_capacity(packet.encoded_len());
+        packet.encode(&mut encoded)?;
+
+        loop {
+            self.writer.write_all(&encoded).await?;
+            self.writer.flush().await?;
+
+            let buf = self.reader.fill_buf().await?;
+            match buf.first() {
+                Some(b'+') => {
+                    self.reader.consume(1);
+                    break;
+                }
+                Some(b'-') => {
+                    self.reader.consume(1);
+                    if !packet.is_valid() {
+                        return Err(Error::InvalidChecksum);
+                    }
+                }
+                _ => break,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{commands::ok_reply, io::GdbServer};
+
+    use std::{
+        io::{self, prelude::*},
+        thread,
+    };
+    use tokio::{
+        io::{AsyncRead, AsyncReadExt},
+        runtime::Handle,
+    };
+
+    /// Blocking access to one half of an async stream, for a server
+    /// running in a thread of its own.
+    struct Blocking<T>(Handle, T);
+    impl<T: AsyncRead + Unpin> Read for Blocking<T> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.block_on(self.1.read(buf))
+        }
+    }
+    impl<T: AsyncWrite + Unpin> Write for Blocking<T> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.block_on(self.1.write(buf))
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            self.0.block_on(self.1.flush())
+        }
+    }
+
+    #[tokio::test]
+    async fn it_talks_to_a_blocking_server() {
+        let (client, server) = tokio::io::duplex(64);
+        let handle = Handle::current();
+        let server = thread::spawn(move || {
+            let (reader, writer) = tokio::io::split(server);
+            let mut server = GdbServer::new(
+                io::BufReader::new(Blocking(handle.clone(), reader)),
+                Blocking(handle, writer),
+            );
+            let packet = server.next_packet().unwrap().unwrap();
+            assert_eq!(packet.data, b"qC");
+            server.dispatch(&ok_reply()).unwrap();
+            assert_eq!(server.next_packet().unwrap(), None);
+        });
+
+        let (reader, writer) = tokio::io::split(client);
+        let mut client = AsyncGdbClient::new(BufReader::new(reader), writer);
+        client
+            .send_packet(&CheckedPacket::from_data(Kind::Packet, b"qC".to_vec()))
+            .await
+            .unwrap();
+        assert_eq!(client.recv_packet().await.unwrap(), Some(ok_reply()));
+        drop(client);
+
+        server.join().unwrap();
+    }
+    #[tokio::test]
+    async fn it_retransmits_denied_packets() {
+        let input: &[u8] = b"-+$E01#a6";
+        let mut client = AsyncGdbClient::new(input, Vec::new());
+        let packet = CheckedPacket::from_data(Kind::Packet, b"g".to_vec());
+        client.send_packet(&packet).await.unwrap();
+        assert_eq!(client.recv_packet().await.unwrap().unwrap().data, b"E01");
+        assert_eq!(client.writer, b"$g#67$g#67+");
+    }
+}
@@ -0,0 +1,1619 @@
+//! Builders and parsers for the individual packets of the protocol,
+//! on top of the raw `CheckedPacket` representation.
+
+use crate::{
+    packet::{self, CheckedPacket, Kind},
+    parser::CHECKSUM_LEN,
+    Error,
+};
+
+use std::num::ParseIntError;
+
+pub mod vfile;
+
+/// Encode bytes as lowercase hexadecimal, two digits per byte.
+///
+/// ```rust
+/// # use gdb_protocol::commands::to_hex;
+/// assert_eq!(to_hex(b"\x00\x7fHi"), b"007f4869".to_vec());
+/// ```
+pub fn to_hex(bytes: &[u8]) -> Vec<u8> {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+    let mut hex = Vec::with_capacity(bytes.len() * 2);
+    for &b in bytes {
+        hex.push(DIGITS[usize::from(b >> 4)]);
+        hex.push(DIGITS[usize::from(b & 0xF)]);
+    }
+    hex
+}
+
+/// Decode hexadecimal (of either case), two digits per byte. An odd
+/// number of digits is rejected as malformed.
+///
+/// ```rust
+/// # use gdb_protocol::commands::from_hex;
+/// assert_eq!(from_hex(b"007F4869").unwrap(), b"\x00\x7fHi".to_vec());
+/// assert!(from_hex(b"123").is_err());
+/// assert!(from_hex(b"zz").is_err());
+/// assert!(from_hex(b"+1").is_err());
+/// ```
+// usize::is_multiple_of needs a newer compiler
+#[allow(clippy::manual_is_multiple_of)]
+pub fn from_hex(hex: &[u8]) -> Result<Vec<u8>, Error> {
+    if hex.len() % 2 != 0 {
+        return Err(Error::Malformed(hex.to_vec()));
+    }
+    hex.chunks(2)
+        .map(|pair| hex_number(pair, u8::from_str_radix))
+        .collect()
+}
+
+/// Parse a hexadecimal number, using the radix parser of the integer
+/// type, such as `u32::from_str_radix`. Only hex digits are accepted,
+/// not the sign `from_str_radix` allows.
+pub(crate) fn hex_number<T>(
+    hex: &[u8],
+    from_str_radix: fn(&str, u32) -> Result<T, ParseIntError>,
+) -> Result<T, Error> {
+    let string = std::str::from_utf8(hex).map_err(|err| Error::non_utf8(hex, err))?;
+    if !hex.iter().all(u8::is_ascii_hexdigit) {
+        // The same error as for any other invalid digit
+        let err = u8::from_str_radix("+", 16).unwrap_err();
+        return Err(Error::non_number(string, err));
+    }
+    from_str_radix(string, 16).map_err(|err| Error::non_number(string, err))
+}
+
+/// Encode the lowest `width` bytes of `value` as hex, in little-endian
+/// byte order, as in register packets of little-endian targets.
+///
+/// ```rust
+/// # use gdb_protocol::commands::reg_to_hex_le;
+/// assert_eq!(reg_to_hex_le(0xdeadbeef, 4), b"efbeadde");
+/// ```
+///
+/// # Panics
+///
+/// Panics if `width` is more than 8, the width of a `u64`.
+pub fn reg_to_hex_le(value: u64, width: usize) -> Vec<u8> {
+    assert!(width <= 8, "registers wider than 64 bits are not supported");
+    to_hex(&value.to_le_bytes()[..width])
+}
+/// Encode the lowest `width` bytes of `value` as hex, in big-endian
+/// byte order, as in register packets of big-endian targets.
+///
+/// ```rust
+/// # use gdb_protocol::commands::reg_to_hex_be;
+/// assert_eq!(reg_to_hex_be(0xdeadbeef, 4), b"deadbeef");
+/// ```
+///
+/// # Panics
+///
+/// Panics if `width` is more than 8, the width of a `u64`.
+pub fn reg_to_hex_be(value: u64, width: usize) -> Vec<u8> {
+    assert!(width <= 8, "registers wider than 64 bits are not supported");
+    to_hex(&value.to_be_bytes()[8 - width..])
+}
+/// Decode a register value from hex in little-endian byte order.
+/// Registers wider than 64 bits are rejected as malformed.
+pub fn reg_from_hex_le(hex: &[u8]) -> Result<u64, Error> {
+    let bytes = from_hex(hex)?;
+    if bytes.len() > 8 {
+        return Err(Error::Malformed(hex.to_vec()));
+    }
+    let mut value = [0; 8];
+    value[..bytes.len()].copy_from_slice(&bytes);
+    Ok(u64::from_le_bytes(value))
+}
+/// Decode a register value from hex in big-endian byte order.
+/// Registers wider than 64 bits are rejected as malformed.
+pub fn reg_from_hex_be(hex: &[u8]) -> Result<u64, Error> {
+    let bytes = from_hex(hex)?;
+    if bytes.len() > 8 {
+        return Err(Error::Malformed(hex.to_vec()));
+    }
+    let mut value = [0; 8];
+    value[8 - bytes.len()..].copy_from_slice(&bytes);
+    Ok(u64::from_be_bytes(value))
+}
+
+fn chunk(data: &[u8], max_size: usize, prefixed: bool) -> Result<Vec<CheckedPacket>, Error> {
+    // $, the optional prefix, #, and the checksum
+    let overhead = 1 + prefixed as usize + 1 + CHECKSUM_LEN as usize;
+    // Any byte must fit, even one that needs escaping
+    if max_size < overhead + 2 {
+        return Err(Error::PacketTooLarge {
+            kind: Kind::Packet,
+            len: overhead + 2,
+            max: max_size,
+        });
+    }
+    let budget = max_size - overhead;
+
+    let mut packets = Vec::new();
+    let mut remaining = data;
+    loop {
+        let mut len = 0;
+        let mut cost = 0;
+        for &b in remaining {
+            let b_cost = if packet::needs_escape(b) { 2 } else { 1 };
+            if cost + b_cost > budget {
+                break;
+            }
+            cost += b_cost;
+            len += 1;
+        }
+        let (piece, rest) = remaining.split_at(len);
+        remaining = rest;
+
+        let mut data = Vec::with_capacity(prefixed as usize + piece.len());
+        if prefixed {
+            data.push(if remaining.is_empty() { b'l' } else { b'm' });
+        }
+        data.extend_from_slice(piece);
+        packets.push(CheckedPacket::from_data(Kind::Packet, data));
+
+        if remaining.is_empty() {
+            break Ok(packets);
+        }
+    }
+}
+
+/// Split a reply into packets with an encoded length of at most
+/// `max_size`, such as the `PacketSize` negotiated with `qSupported`.
+/// Following the `qXfer` convention, each packet is prefixed with `m`
+/// if more data follows, or `l` if it is the last one.
+///
+/// Fails with `Error::PacketTooLarge` if `max_size` can't fit a
+/// packet carrying an escaped byte, with the smallest size that
+/// would fit as `len`.
+pub fn chunk_reply(data: &[u8], max_size: usize) -> Result<Vec<CheckedPacket>, Error> {
+    chunk(data, max_size, true)
+}
+/// Like `chunk_reply`, but without adding any prefix.
+pub fn chunk_data(data: &[u8], max_size: usize) -> Result<Vec<CheckedPacket>, Error> {
+    chunk(data, max_size, false)
+}
+
+/// Build an `O` packet, used by the stub to forward output of the
+/// inferior to GDB's console.
+pub fn output(text: &[u8]) -> CheckedPacket {
+    let mut data = Vec::with_capacity(1 + text.len() * 2);
+    data.push(b'O');
+    data.extend_from_slice(&to_hex(text));
+    CheckedPacket::from_data(Kind::Packet, data)
+}
+
+/// Decode the console output carried by an `O` packet.
+pub fn parse_output(packet: &CheckedPacket) -> Result<Vec<u8>, Error> {
+    match packet.data.split_first() {
+        Some((b'O', hex)) => from_hex(hex),
+        _ => Err(Error::Malformed(packet.data.clone())),
+    }
+}
+
+/// A command without any fields worth their own type, recognized by
+/// `parse`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "unstable", non_exhaustive)]
+pub enum Command {
+    /// The `!` packet, enabling extended mode. Reply with `OK`.
+    ExtendedMode,
+    /// The `?` packet, asking why the target stopped, which GDB sends
+    /// right after connecting. Reply with a `StopReply`.
+    HaltReason,
+    /// The `R XX` packet, restarting the program in extended mode. The
+    /// argument has no meaning. Per the specification, this packet
+    /// must not be replied to.
+    Restart { arg: u8 },
+    /// The `D` packet, detaching from the target, or `D;pid` to detach
+    /// from process `pid` only when the multiprocess extensions are in
+    /// use. Reply with `ok_reply`, or `error_reply` on failure.
+    Detach { pid: Option<u32> },
+    /// The `qAttached` packet, or `qAttached:pid` when the
+    /// multiprocess extensions are in use, asking whether the stub
+    /// attached to an existing process or created a new one. Reply
+    /// with `attached_reply`.
+    QueryAttached { pid: Option<u32> },
+    /// The `qC` packet, asking for the current thread. Reply with
+    /// `current_thread_reply`.
+    CurrentThread,
+    /// The `T thread-id` packet, asking whether a thread is still
+    /// alive. Reply with `ok_reply` if it is, or `error_reply` if it
+    /// is gone. Like the rest of `parse`, this is only meant for packets
+    /// received from GDB.
+    ThreadAlive { thread: ThreadId },
+    /// The `QNonStop:1` and `QNonStop:0` packets, entering or leaving
+    /// non-stop mode, where stop events are reported as `%Stop`
+    /// notifications. Reply with `ok_reply`, see
+    /// `io::GdbServer::set_non_stop`.
+    SetNonStop { enable: bool },
+    /// Any of the `qT` and `QT` tracepoint packets, such as `qTStatus`
+    /// or `QTinit`, with the name after the `qT` or `QT`. GDB sends
+    /// some of them even to stubs without tracepoint support, which
+    /// should `reply_unsupported` to all of them. The `qThread` and
+    /// `qTLSAddr` packets aren't tracepoint packets.
+    Tracepoint(Vec<u8>),
+}
+
+/// Recognize a `Command`. Returns `None` for any other packet, which
+/// may be one of the packets with a dedicated type in this module.
+pub fn parse(packet: &CheckedPacket) -> Result<Option<Command>, Error> {
+    let data = &packet.data[..];
+    Ok(Some(match data.split_first() {
+        Some((b'!', b"")) => Command::ExtendedMode,
+        Some((b'?', b"")) => Command::HaltReason,
+        Some((b'R', arg)) => Command::Restart {
+            arg: hex_number(arg, u8::from_str_radix)?,
+        },
+        Some((b'D', b"")) => Command::Detach { pid: None },
+        Some((b'D', rest)) => match rest.split_first() {
+            Some((b';', pid)) => Command::Detach {
+                pid: Some(hex_number(pid, u32::from_str_radix)?),
+            },
+            _ => return Ok(None),
+        },
+        Some((b'T', thread)) => match ThreadId::parse(thread) {
+            Ok(thread) => Command::ThreadAlive { thread },
+            Err(_) => return Ok(None),
+        },
+        Some((b'Q', rest)) if rest.starts_with(b"NonStop:") => Command::SetNonStop {
+            enable: match &rest[b"NonStop:".len()..] {
+                b"0" => false,
+                b"1" => true,
+                _ => return Err(Error::Malformed(data.to_vec())),
+            },
+        },
+        Some((b'q', rest)) | Some((b'Q', rest))
+            if rest.starts_with(b"T")
+                && !rest.starts_with(b"Thread")
+                && !rest.starts_with(b"TLSAddr") =>
+        {
+            Command::Tracepoint(rest[1..].to_vec())
+        }
+        Some((b'q', b"C")) => Command::CurrentThread,
+        Some((b'q', b"Attached")) => Command::QueryAttached { pid: None },
+        Some((b'q', rest)) if rest.starts_with(b"Attached:") => Command::QueryAttached {
+            pid: Some(hex_number(
+                &rest[b"Attached:".len()..],
+                u32::from_str_radix,
+            )?),
+        },
+        _ => return Ok(None),
+    }))
+}
+
+/// Build a reply to `Command::QueryAttached`: `1` if the stub attached
+/// to an existing process, which is then detached from rather than
+/// killed when GDB quits, or `0` if it created the process.
+pub fn attached_reply(attached: bool) -> CheckedPacket {
+    let data = if attached { b"1" } else { b"0" };
+    CheckedPacket::from_data(Kind::Packet, data.to_vec())
+}
+/// Build a reply to `Command::CurrentThread`, `QC` followed by the
+/// thread-id.
+pub fn current_thread_reply(thread: ThreadId) -> CheckedPacket {
+    let mut data = b"QC".to_vec();
+    data.extend_from_slice(&thread.encode());
+    CheckedPacket::from_data(Kind::Packet, data)
+}
+
+/// The `vKill;pid` packet, killing process `pid` when the
+/// multiprocess extensions are in use. Reply with `ok_reply`, or
+/// `error_reply` on failure.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Kill {
+    pub pid: u32,
+}
+impl Kill {
+    pub fn from_packet(packet: &CheckedPacket) -> Result<Self, Error> {
+        match packet.data.strip_prefix(b"vKill;") {
+            Some(pid) => Ok(Self {
+                pid: hex_number(pid, u32::from_str_radix)?,
+            }),
+            None => Err(Error::Malformed(packet.data.clone())),
+        }
+    }
+    pub fn to_packet(&self) -> CheckedPacket {
+        CheckedPacket::from_data(Kind::Packet, format!("vKill;{:x}", self.pid).into_bytes())
+    }
+}
+
+/// The `QPassSignals:sig;sig...` packet, listing the signals to pass
+/// to the program without stopping, or `QProgramSignals:sig;sig...`
+/// if `program` is set, listing the signals the program may receive at
+/// all. Either replaces the previous list, and an empty one means no
+/// signals. Reply with `ok_reply`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignalList {
+    pub program: bool,
+    pub signals: Vec<u8>,
+}
+impl SignalList {
+    pub fn from_packet(packet: &CheckedPacket) -> Result<Self, Error> {
+        let (program, list) = if let Some(list) = packet.data.strip_prefix(b"QPassSignals:") {
+            (false, list)
+        } else if let Some(list) = packet.data.strip_prefix(b"QProgramSignals:") {
+            (true, list)
+        } else {
+            return Err(Error::Malformed(packet.data.clone()));
+        };
+        let signals = if list.is_empty() {
+            Vec::new()
+        } else {
+            list.split(|&b| b == b';')
+                .map(|signal| hex_number(signal, u8::from_str_radix))
+                .collect::<Result<_, _>>()?
+        };
+        Ok(Self { program, signals })
+    }
+    pub fn to_packet(&self) -> CheckedPacket {
+        let mut data = if self.program {
+            b"QProgramSignals:".to_vec()
+        } else {
+            b"QPassSignals:".to_vec()
+        };
+        for (i, signal) in self.signals.iter().enumerate() {
+            if i > 0 {
+                data.push(b';');
+            }
+            data.extend_from_slice(format!("{:x}", signal).as_bytes());
+        }
+        CheckedPacket::from_data(Kind::Packet, data)
+    }
+}
+
+/// Builders for the stop replies, which report why the target stopped,
+/// in reply to `?` or to the packets resuming execution:
+///
+/// ```rust
+/// # use gdb_protocol::{commands::{self, Command, StopReply}, io::GdbServer, Error};
+/// # let mut input: &[u8] = b"$?#3f+";
+/// # let mut server = GdbServer::tester(&mut input);
+/// let packet = server.next_packet()?.unwrap();
+/// if let Some(Command::HaltReason) = commands::parse(&packet)? {
+///     server.dispatch(&StopReply::signal(5))?;
+/// }
+/// # assert_eq!(server.response(), b"+$S05#b8");
+/// # Ok::<(), Error>(())
+/// ```
+pub struct StopReply;
+impl StopReply {
+    /// The `S AA` reply, the target stopped because of signal `AA`.
+    pub fn signal(signal: u8) -> CheckedPacket {
+        CheckedPacket::from_data(Kind::Packet, format!("S{:02x}", signal).into_bytes())
+    }
+    /// The `W AA` reply, the process exited with status `AA`.
+    pub fn exited(status: u8) -> CheckedPacket {
+        CheckedPacket::from_data(Kind::Packet, format!("W{:02x}", status).into_bytes())
+    }
+    /// The `X AA` reply, the process terminated because of signal
+    /// `AA`.
+    pub fn terminated(signal: u8) -> CheckedPacket {
+        CheckedPacket::from_data(Kind::Packet, format!("X{:02x}", signal).into_bytes())
+    }
+}
+
+/// The reply to a packet which isn't supported, which is an empty
+/// packet. Note that not every empty reply means this: some commands
+/// may legitimately reply with empty data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Unsupported;
+impl Unsupported {
+    pub fn to_packet(&self) -> CheckedPacket {
+        CheckedPacket::empty()
+    }
+}
+
+/// Build the `OK` reply, acknowledging a successful command.
+pub fn ok_reply() -> CheckedPacket {
+    CheckedPacket::from_data(Kind::Packet, b"OK".to_vec())
+}
+
+/// Build an `Enn` reply, reporting that a command failed with the
+/// error number `errno`.
+pub fn error_reply(errno: u8) -> CheckedPacket {
+    CheckedPacket::from_data(Kind::Packet, format!("E{:02x}", errno).into_bytes())
+}
+
+/// Return the error number of an `Enn` reply, or `None` if the packet
+/// is not one.
+pub fn parse_error_reply(packet: &CheckedPacket) -> Option<u8> {
+    match packet.data.split_first() {
+        Some((b'E', errno)) if errno.len() == 2 => hex_number(errno, u8::from_str_radix).ok(),
+        _ => None,
+    }
+}
+
+/// The `p n` packet, reading the value of register `n`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReadRegister {
+    pub reg: u32,
+}
+impl ReadRegister {
+    pub fn from_packet(packet: &CheckedPacket) -> Result<Self, Error> {
+        match packet.data.split_first() {
+            Some((b'p', reg)) => Ok(Self {
+                reg: hex_number(reg, u32::from_str_radix)?,
+            }),
+            _ => Err(Error::Malformed(packet.data.clone())),
+        }
+    }
+    pub fn to_packet(&self) -> CheckedPacket {
+        CheckedPacket::from_data(Kind::Packet, format!("p{:x}", self.reg).into_bytes())
+    }
+}
+
+/// The `P n=r` packet, writing `value` to register `n`. The value is
+/// the raw bytes of the register in target byte order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WriteRegister {
+    pub reg: u32,
+    pub value: Vec<u8>,
+}
+impl WriteRegister {
+    pub fn from_packet(packet: &CheckedPacket) -> Result<Self, Error> {
+        let malformed = || Error::Malformed(packet.data.clone());
+
+        let rest = match packet.data.split_first() {
+            Some((b'P', rest)) => rest,
+            _ => return Err(malformed()),
+        };
+        let eq = memchr::memchr(b'=', rest).ok_or_else(malformed)?;
+        Ok(Self {
+            reg: hex_number(&rest[..eq], u32::from_str_radix)?,
+            value: from_hex(&rest[eq + 1..])?,
+        })
+    }
+    pub fn to_packet(&self) -> CheckedPacket {
+        let mut data = format!("P{:x}=", self.reg).into_bytes();
+        data.extend_from_slice(&to_hex(&self.value));
+        CheckedPacket::from_data(Kind::Packet, data)
+    }
+}
+
+fn parse_resume(
+    packet: &CheckedPacket,
+    plain: u8,
+    with_signal: u8,
+) -> Result<(Option<u8>, Option<u64>), Error> {
+    let malformed = || Error::Malformed(packet.data.clone());
+
+    let (&first, rest) = packet.data.split_first().ok_or_else(malformed)?;
+    let (signal, addr) = if first == plain {
+        (None, Some(rest).filter(|addr| !addr.is_empty()))
+    } else if first == with_signal {
+        match memchr::memchr(b';', rest) {
+            Some(semicolon) => (Some(&rest[..semicolon]), Some(&rest[semicolon + 1..])),
+            None => (Some(rest), None),
+        }
+    } else {
+        return Err(malformed());
+    };
+
+    Ok((
+        signal
+            .map(|signal| hex_number(signal, u8::from_str_radix))
+            .transpose()?,
+        addr.map(|addr| hex_number(addr, u64::from_str_radix))
+            .transpose()?,
+    ))
+}
+fn resume_packet(
+    plain: u8,
+    with_signal: u8,
+    signal: Option<u8>,
+    addr: Option<u64>,
+) -> CheckedPacket {
+    let mut data = match signal {
+        Some(signal) => format!("{}{:02x}", with_signal as char, signal),
+        None => (plain as char).to_string(),
+    };
+    if let Some(addr) = addr {
+        if signal.is_some() {
+            data.push(';');
+        }
+        data.push_str(&format!("{:x}", addr));
+    }
+    CheckedPacket::from_data(Kind::Packet, data.into_bytes())
+}
+
+/// The `g` packet, reading all registers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReadRegisters;
+impl ReadRegisters {
+    pub fn from_packet(packet: &CheckedPacket) -> Result<Self, Error> {
+        match &packet.data[..] {
+            b"g" => Ok(Self),
+            _ => Err(Error::Malformed(packet.data.clone())),
+        }
+    }
+    pub fn to_packet(&self) -> CheckedPacket {
+        CheckedPacket::from_data(Kind::Packet, b"g".to_vec())
+    }
+}
+
+/// The `G XX...` packet, writing all registers. The raw bytes of the
+/// register file are returned as-is, to be sliced up according to the
+/// register layout of the target.
+pub struct WriteRegisters;
+impl WriteRegisters {
+    pub fn from_packet(packet: &CheckedPacket) -> Result<Vec<u8>, Error> {
+        match packet.data.split_first() {
+            Some((b'G', hex)) => from_hex(hex),
+            _ => Err(Error::Malformed(packet.data.clone())),
+        }
+    }
+    pub fn to_packet(registers: &[u8]) -> CheckedPacket {
+        let mut data = Vec::with_capacity(1 + registers.len() * 2);
+        data.push(b'G');
+        data.extend_from_slice(&to_hex(registers));
+        CheckedPacket::from_data(Kind::Packet, data)
+    }
+}
+
+/// The reply to `ReadRegisters` and `ReadRegister`, the raw bytes of
+/// the registers in hex, without any prefix.
+pub struct RegisterReply;
+impl RegisterReply {
+    /// Decodes the register bytes. Register data may well start with
+    /// `E`, so only the exact shape of an error reply (see
+    /// `parse_error_reply`) is taken to be one, failing with
+    /// `Error::Malformed`.
+    pub fn parse(packet: &CheckedPacket) -> Result<Vec<u8>, Error> {
+        if parse_error_reply(packet).is_some() {
+            return Err(Error::Malformed(packet.data.clone()));
+        }
+        from_hex(&packet.data)
+    }
+    pub fn to_packet(registers: &[u8]) -> CheckedPacket {
+        CheckedPacket::from_data(Kind::Packet, to_hex(registers))
+    }
+}
+
+/// The `c [addr]` and `C sig[;addr]` packets, resuming execution at
+/// `addr` (or where the target stopped), optionally with a signal.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Continue {
+    pub signal: Option<u8>,
+    pub addr: Option<u64>,
+}
+impl Continue {
+    pub fn from_packet(packet: &CheckedPacket) -> Result<Self, Error> {
+        let (signal, addr) = parse_resume(packet, b'c', b'C')?;
+        Ok(Self { signal, addr })
+    }
+    pub fn to_packet(&self) -> CheckedPacket {
+        resume_packet(b'c', b'C', self.signal, self.addr)
+    }
+}
+
+/// The `s [addr]` and `S sig[;addr]` packets, stepping a single
+/// instruction at `addr` (or where the target stopped), optionally
+/// with a signal.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Step {
+    pub signal: Option<u8>,
+    pub addr: Option<u64>,
+}
+impl Step {
+    pub fn from_packet(packet: &CheckedPacket) -> Result<Self, Error> {
+        let (signal, addr) = parse_resume(packet, b's', b'S')?;
+        Ok(Self { signal, addr })
+    }
+    pub fn to_packet(&self) -> CheckedPacket {
+        resume_packet(b's', b'S', self.signal, self.addr)
+    }
+}
+
+/// The `qRcmd,command` packet, which GDB sends for `monitor command`.
+pub struct MonitorCommand;
+impl MonitorCommand {
+    /// Return the raw bytes of the command.
+    pub fn from_packet(packet: &CheckedPacket) -> Result<Vec<u8>, Error> {
+        if !packet.data.starts_with(b"qRcmd,") {
+            return Err(Error::Malformed(packet.data.clone()));
+        }
+        from_hex(&packet.data[b"qRcmd,".len()..])
+    }
+    pub fn to_packet(command: &[u8]) -> CheckedPacket {
+        let mut data = b"qRcmd,".to_vec();
+        data.extend_from_slice(&to_hex(command));
+        CheckedPacket::from_data(Kind::Packet, data)
+    }
+    /// Build the packets replying to a command: its output, if any, as
+    /// an `O` packet, followed by `OK`.
+    pub fn reply(text: &[u8]) -> Vec<CheckedPacket> {
+        let mut packets = Vec::with_capacity(2);
+        if !text.is_empty() {
+            packets.push(output(text));
+        }
+        packets.push(ok_reply());
+        packets
+    }
+}
+
+/// The `qSymbol` packets, with which the stub can look up symbols
+/// in the program loaded into GDB. GDB sends an `Offer`, which the
+/// stub replies to with `ok_reply` if it needs no symbols, or with a
+/// `Request` for one. GDB replies to that with its `Value`, to which
+/// the stub replies the same way as to the offer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "unstable", non_exhaustive)]
+pub enum SymbolLookup {
+    /// `qSymbol::`, from GDB
+    Offer,
+    /// `qSymbol:name`, from the stub, with the name hex-encoded
+    Request { name: Vec<u8> },
+    /// `qSymbol:value:name`, from GDB, with the name hex-encoded. The
+    /// value is empty if GDB doesn't know the symbol, unlike in an
+    /// `Offer` where the name is empty as well.
+    Value { name: Vec<u8>, value: Option<u64> },
+}
+impl SymbolLookup {
+    pub fn from_packet(packet: &CheckedPacket) -> Result<Self, Error> {
+        let malformed = || Error::Malformed(packet.data.clone());
+
+        let rest = packet
+            .data
+            .strip_prefix(b"qSymbol:")
+            .ok_or_else(malformed)?;
+        match memchr::memchr(b':', rest) {
+            None if rest.is_empty() => Err(malformed()),
+            None => Ok(SymbolLookup::Request {
+                name: from_hex(rest)?,
+            }),
+            Some(_) if rest == b":" => Ok(SymbolLookup::Offer),
+            Some(colon) => Ok(SymbolLookup::Value {
+                name: from_hex(&rest[colon + 1..])?,
+                value: match &rest[..colon] {
+                    b"" => None,
+                    value => Some(hex_number(value, u64::from_str_radix)?),
+                },
+            }),
+        }
+    }
+    pub fn to_packet(&self) -> CheckedPacket {
+        let mut data = b"qSymbol:".to_vec();
+        match self {
+            SymbolLookup::Offer => data.push(b':'),
+            SymbolLookup::Request { name } => data.extend_from_slice(&to_hex(name)),
+            SymbolLookup::Value { name, value } => {
+                if let Some(value) = value {
+                    data.extend_from_slice(format!("{:x}", value).as_bytes());
+                }
+                data.push(b':');
+                data.extend_from_slice(&to_hex(name));
+            }
+        }
+        CheckedPacket::from_data(Kind::Packet, data)
+    }
+}
+
+/// The `X addr,length:XX...` packet, writing `data` to memory at
+/// `addr`. The data is binary, escaped by the packet encoding.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BinaryWrite {
+    pub addr: u64,
+    pub data: Vec<u8>,
+}
+impl BinaryWrite {
+    pub fn from_packet(packet: &CheckedPacket) -> Result<Self, Error> {
+        let malformed = || Error::Malformed(packet.data.clone());
+
+        let rest = match packet.data.split_first() {
+            Some((b'X', rest)) => rest,
+            _ => return Err(malformed()),
+        };
+        let colon = memchr::memchr(b':', rest).ok_or_else(malformed)?;
+        let comma = memchr::memchr(b',', &rest[..colon]).ok_or_else(malformed)?;
+        let len = hex_number(&rest[comma + 1..colon], usize::from_str_radix)?;
+        let data = &rest[colon + 1..];
+        if data.len() != len {
+            return Err(malformed());
+        }
+        Ok(Self {
+            addr: hex_number(&rest[..comma], u64::from_str_radix)?,
+            data: data.to_vec(),
+        })
+    }
+    pub fn to_packet(&self) -> CheckedPacket {
+        let mut data = format!("X{:x},{:x}:", self.addr, self.data.len()).into_bytes();
+        data.extend_from_slice(&self.data);
+        CheckedPacket::from_data(Kind::Packet, data)
+    }
+}
+
+/// Merge writes to contiguous memory, so they take fewer round trips,
+/// as long as the merged data is at most `max_len` bytes long. The
+/// writes are sorted by address first. Overlapping writes are
+/// ambiguous, and fail with `Error::Malformed` holding the latter
+/// write's packet data.
+pub fn coalesce_writes(writes: &[BinaryWrite], max_len: usize) -> Result<Vec<BinaryWrite>, Error> {
+    let mut sorted: Vec<&BinaryWrite> = writes.iter().collect();
+    sorted.sort_by_key(|write| write.addr);
+
+    let mut merged: Vec<BinaryWrite> = Vec::new();
+    for write in sorted {
+        if let Some(last) = merged.last_mut() {
+            let end = last.addr.saturating_add(last.data.len() as u64);
+            if write.addr < end {
+                return Err(Error::Malformed(write.to_packet().invalidate_check().data));
+            }
+            if write.addr == end && last.data.len() + write.data.len() <= max_len {
+                last.data.extend_from_slice(&write.data);
+                continue;
+            }
+        }
+        merged.push(write.clone());
+    }
+    Ok(merged)
+}
+
+/// Shorthands for the packets of `vfile::Reply`, the
+/// `F retcode[,errno][;attachment]` replies to the `vFile` requests.
+/// Attachments are binary, and escaped when the packet is encoded like
+/// all packet data, so they're passed in raw.
+pub struct FileIoReply;
+impl FileIoReply {
+    pub fn success(retcode: i64) -> CheckedPacket {
+        vfile::Reply::ok(retcode).to_packet()
+    }
+    /// Build the reply to a failed request, with a return code of `-1`
+    /// and the GDB `errno`, which isn't necessarily the host's.
+    pub fn error(errno: u32) -> CheckedPacket {
+        vfile::Reply::error(errno).to_packet()
+    }
+    /// Build the reply to a request returning data, such as
+    /// `vFile:pread`, where `retcode` is usually the length of the
+    /// attachment.
+    pub fn with_attachment(retcode: i64, attachment: &[u8]) -> CheckedPacket {
+        let reply = vfile::Reply {
+            result: retcode,
+            ..vfile::Reply::data(attachment)
+        };
+        reply.to_packet()
+    }
+}
+
+/// The type of a breakpoint or watchpoint, the first field of the
+/// `Z` and `z` packets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "unstable", non_exhaustive)]
+pub enum BpType {
+    Software,
+    Hardware,
+    WriteWatchpoint,
+    ReadWatchpoint,
+    AccessWatchpoint,
+}
+impl BpType {
+    fn from_digit(digit: u8) -> Option<Self> {
+        match digit {
+            0 => Some(BpType::Software),
+            1 => Some(BpType::Hardware),
+            2 => Some(BpType::WriteWatchpoint),
+            3 => Some(BpType::ReadWatchpoint),
+            4 => Some(BpType::AccessWatchpoint),
+            _ => None,
+        }
+    }
+    fn to_digit(self) -> u8 {
+        match self {
+            BpType::Software => 0,
+            BpType::Hardware => 1,
+            BpType::WriteWatchpoint => 2,
+            BpType::ReadWatchpoint => 3,
+            BpType::AccessWatchpoint => 4,
+        }
+    }
+}
+
+/// The `Z type,addr,kind` and `z type,addr,kind` packets, setting or
+/// clearing a breakpoint or watchpoint at `addr`. The meaning of
+/// `kind` depends on the type, usually the size of the breakpoint
+/// instruction or of the watched memory. Any conditions or commands
+/// following a `;` are kept as raw bytes in `tail`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub set: bool,
+    pub bp_type: BpType,
+    pub addr: u64,
+    pub kind: u32,
+    pub tail: Vec<u8>,
+}
+impl Breakpoint {
+    pub fn from_packet(packet: &CheckedPacket) -> Result<Self, Error> {
+        let malformed = || Error::Malformed(packet.data.clone());
+
+        let (set, rest) = match packet.data.split_first() {
+            Some((b'Z', rest)) => (true, rest),
+            Some((b'z', rest)) => (false, rest),
+            _ => return Err(malformed()),
+        };
+        let (fields, tail) = match memchr::memchr(b';', rest) {
+            Some(semicolon) => (&rest[..semicolon], &rest[semicolon + 1..]),
+            None => (rest, &[][..]),
+        };
+        let fields: Vec<&[u8]> = fields.split(|&b| b == b',').collect();
+        if fields.len() != 3 {
+            return Err(malformed());
+        }
+        Ok(Self {
+            set,
+            bp_type: BpType::from_digit(hex_number(fields[0], u8::from_str_radix)?)
+                .ok_or_else(malformed)?,
+            addr: hex_number(fields[1], u64::from_str_radix)?,
+            kind: hex_number(fields[2], u32::from_str_radix)?,
+            tail: tail.to_vec(),
+        })
+    }
+    pub fn to_packet(&self) -> CheckedPacket {
+        let mut data = format!(
+            "{}{},{:x},{:x}",
+            if self.set { 'Z' } else { 'z' },
+            self.bp_type.to_digit(),
+            self.addr,
+            self.kind
+        )
+        .into_bytes();
+        if !self.tail.is_empty() {
+            data.push(b';');
+            data.extend_from_slice(&self.tail);
+        }
+        CheckedPacket::from_data(Kind::Packet, data)
+    }
+}
+
+/// A process or thread id, as part of a `ThreadId`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Id {
+    /// `-1`, meaning all processes or threads.
+    All,
+    /// `0`, meaning an arbitrary process or thread.
+    Any,
+    Id(u64),
+}
+impl Id {
+    fn parse(hex: &[u8]) -> Result<Self, Error> {
+        match hex {
+            b"-1" => Ok(Id::All),
+            _ => match hex_number(hex, u64::from_str_radix)? {
+                0 => Ok(Id::Any),
+                id => Ok(Id::Id(id)),
+            },
+        }
+    }
+    fn encode(self, data: &mut Vec<u8>) {
+        match self {
+            Id::All => data.extend_from_slice(b"-1"),
+            Id::Any => data.push(b'0'),
+            Id::Id(id) => data.extend_from_slice(format!("{:x}", id).as_bytes()),
+        }
+    }
+}
+
+/// A thread-id, either `tid` or `p<pid>.<tid>` when the multiprocess
+/// extensions are in use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ThreadId {
+    pub pid: Option<Id>,
+    pub tid: Id,
+}
+impl ThreadId {
+    /// A thread-id without a process.
+    pub fn thread(tid: u64) -> Self {
+        Self {
+            pid: None,
+            tid: Id::Id(tid),
+        }
+    }
+    pub fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        match bytes.split_first() {
+            Some((b'p', rest)) => {
+                let (pid, tid) = match memchr::memchr(b'.', rest) {
+                    Some(dot) => (&rest[..dot], Id::parse(&rest[dot + 1..])?),
+                    // A process without a thread means all its threads
+                    None => (rest, Id::All),
+                };
+                Ok(Self {
+                    pid: Some(Id::parse(pid)?),
+                    tid,
+                })
+            }
+            _ => Ok(Self {
+                pid: None,
+                tid: Id::parse(bytes)?,
+            }),
+        }
+    }
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        if let Some(pid) = self.pid {
+            data.push(b'p');
+            pid.encode(&mut data);
+            data.push(b'.');
+        }
+        self.tid.encode(&mut data);
+        data
+    }
+}
+
+/// The `qfThreadInfo` and `qsThreadInfo` packets, with which GDB
+/// enumerates the threads of the target: the first packet asks for
+/// the first chunk, and the subsequent ones for the following chunks,
+/// until the reply is `l`. See `thread_info_reply`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ThreadInfo {
+    First,
+    Subsequent,
+}
+impl ThreadInfo {
+    pub fn from_packet(packet: &CheckedPacket) -> Result<Self, Error> {
+        match &packet.data[..] {
+            b"qfThreadInfo" => Ok(ThreadInfo::First),
+            b"qsThreadInfo" => Ok(ThreadInfo::Subsequent),
+            _ => Err(Error::Malformed(packet.data.clone())),
+        }
+    }
+    pub fn to_packet(&self) -> CheckedPacket {
+        let data = match self {
+            ThreadInfo::First => b"qfThreadInfo".to_vec(),
+            ThreadInfo::Subsequent => b"qsThreadInfo".to_vec(),
+        };
+        CheckedPacket::from_data(Kind::Packet, data)
+    }
+}
+
+/// Build a reply to `ThreadInfo`, listing `ids` after `m`, or after
+/// `l` if `last` is set. GDB stops reading at `l`, so the last ids
+/// should be sent with `last` unset, and the following query replied
+/// to with no ids and `last` set. Use `thread_info_replies` to build
+/// all of the replies at once.
+pub fn thread_info_reply(ids: &[ThreadId], last: bool) -> CheckedPacket {
+    let mut data = vec![if last { b'l' } else { b'm' }];
+    for (i, id) in ids.iter().enumerate() {
+        if i > 0 {
+            data.push(b',');
+        }
+        data.extend_from_slice(&id.encode());
+    }
+    CheckedPacket::from_data(Kind::Packet, data)
+}
+/// Build all the replies to `ThreadInfo` listing `ids`, in order, with
+/// an encoded length of at most `max_size` (unless a single id doesn't
+/// fit). The last reply is always an empty `l`, so an empty list is
+/// ended immediately.
+pub fn thread_info_replies(ids: &[ThreadId], max_size: usize) -> Vec<CheckedPacket> {
+    let mut packets = Vec::new();
+    let mut start = 0;
+    while start < ids.len() {
+        let mut end = start + 1;
+        while end < ids.len()
+            && thread_info_reply(&ids[start..=end], false).encoded_len() <= max_size
+        {
+            end += 1;
+        }
+        packets.push(thread_info_reply(&ids[start..end], false));
+        start = end;
+    }
+    packets.push(thread_info_reply(&[], true));
+    packets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_round_trips_output() {
+        let packet = output(b"Hello\n");
+        assert_eq!(packet.data, b"O48656c6c6f0a");
+        assert_eq!(parse_output(&packet).unwrap(), b"Hello\n");
+    }
+    #[test]
+    fn it_rejects_non_output() {
+        let packet = CheckedPacket::from_data(Kind::Packet, b"OK".to_vec());
+        assert!(parse_output(&packet).is_err());
+        assert!(parse_output(&CheckedPacket::empty()).is_err());
+    }
+    #[test]
+    fn it_parses_register_packets() {
+        let packet = CheckedPacket::from_data(Kind::Packet, b"pf".to_vec());
+        let read = ReadRegister::from_packet(&packet).unwrap();
+        assert_eq!(read, ReadRegister { reg: 15 });
+        assert_eq!(read.to_packet(), packet);
+
+        let packet = CheckedPacket::from_data(Kind::Packet, b"P10=0102030405060708".to_vec());
+        let write = WriteRegister::from_packet(&packet).unwrap();
+        assert_eq!(
+            write,
+            WriteRegister {
+                reg: 16,
+                value: vec![1, 2, 3, 4, 5, 6, 7, 8],
+            }
+        );
+        assert_eq!(write.to_packet(), packet);
+    }
+    #[test]
+    fn it_rejects_malformed_register_packets() {
+        for data in &[&b"p"[..], b"pzz", b"g", b""] {
+            let packet = CheckedPacket::from_data(Kind::Packet, data.to_vec());
+            assert!(ReadRegister::from_packet(&packet).is_err(), "{:?}", data);
+        }
+        for data in &[&b"P10"[..], b"P10=123", b"P=00", b"Pzz=00", b"p10=00"] {
+            let packet = CheckedPacket::from_data(Kind::Packet, data.to_vec());
+            assert!(WriteRegister::from_packet(&packet).is_err(), "{:?}", data);
+        }
+    }
+    #[test]
+    fn it_reports_bad_hex_consistently() {
+        match from_hex(b"0g") {
+            Err(Error::NonNumber(string, _)) => assert_eq!(string, "0g"),
+            result => panic!("Expected error NonNumber, got {:?}", result),
+        }
+        match hex_number(b"\xff", u32::from_str_radix) {
+            Err(Error::NonUtf8(bytes, _)) => assert_eq!(bytes, b"\xff"),
+            result => panic!("Expected error NonUtf8, got {:?}", result),
+        }
+        for hex in &[&b"+1"[..], b"-1", b" 1"] {
+            match hex_number(hex, i32::from_str_radix) {
+                Err(Error::NonNumber(string, _)) => assert_eq!(string.as_bytes(), *hex),
+                result => panic!("Expected error NonNumber, got {:?}", result),
+            }
+        }
+    }
+    #[test]
+    fn it_builds_replies() {
+        let mut encoded = Vec::new();
+        error_reply(5).encode(&mut encoded).unwrap();
+        assert_eq!(encoded, b"$E05#aa");
+        assert_eq!(parse_error_reply(&error_reply(5)), Some(5));
+        assert_eq!(parse_error_reply(&error_reply(0xff)), Some(0xff));
+
+        assert_eq!(ok_reply().data, b"OK");
+        assert_eq!(parse_error_reply(&ok_reply()), None);
+        for data in &[&b"E"[..], b"E1", b"E123", b"Ezz"] {
+            let packet = CheckedPacket::from_data(Kind::Packet, data.to_vec());
+            assert_eq!(parse_error_reply(&packet), None, "{:?}", data);
+        }
+    }
+    #[test]
+    fn it_parses_monitor_commands() {
+        let packet = CheckedPacket::from_data(Kind::Packet, b"qRcmd,7265736574".to_vec());
+        assert_eq!(MonitorCommand::from_packet(&packet).unwrap(), b"reset");
+        assert_eq!(MonitorCommand::to_packet(b"reset"), packet);
+
+        for data in &[&b"qRcmd"[..], b"qRcmd7265736574", b"qRcmd,726", b"qC"] {
+            let packet = CheckedPacket::from_data(Kind::Packet, data.to_vec());
+            assert!(MonitorCommand::from_packet(&packet).is_err(), "{:?}", data);
+        }
+    }
+    #[test]
+    fn it_replies_to_monitor_commands() {
+        assert_eq!(
+            MonitorCommand::reply(b"done\n"),
+            vec![output(b"done\n"), ok_reply()]
+        );
+        assert_eq!(MonitorCommand::reply(b""), vec![ok_reply()]);
+    }
+    #[test]
+    fn it_parses_symbol_lookups() {
+        let packet = CheckedPacket::from_data(Kind::Packet, b"qSymbol::".to_vec());
+        assert_eq!(
+            SymbolLookup::from_packet(&packet).unwrap(),
+            SymbolLookup::Offer
+        );
+        assert_eq!(SymbolLookup::Offer.to_packet(), packet);
+
+        let cases = [
+            (
+                &b"qSymbol:6d61696e"[..],
+                SymbolLookup::Request {
+                    name: b"main".to_vec(),
+                },
+            ),
+            (
+                b"qSymbol:8000:6d61696e",
+                SymbolLookup::Value {
+                    name: b"main".to_vec(),
+                    value: Some(0x8000),
+                },
+            ),
+            (
+                b"qSymbol::6d61696e",
+                SymbolLookup::Value {
+                    name: b"main".to_vec(),
+                    value: None,
+                },
+            ),
+        ];
+        for (data, lookup) in &cases {
+            let packet = CheckedPacket::from_data(Kind::Packet, data.to_vec());
+            assert_eq!(&SymbolLookup::from_packet(&packet).unwrap(), lookup);
+            assert_eq!(lookup.to_packet(), packet);
+        }
+
+        for data in &[
+            &b"qSymbol"[..],
+            b"qSymbol:",
+            b"qSymbol:6d6",
+            b"qSymbol:x:6d61696e",
+        ] {
+            let packet = CheckedPacket::from_data(Kind::Packet, data.to_vec());
+            assert!(SymbolLookup::from_packet(&packet).is_err(), "{:?}", data);
+        }
+    }
+    #[test]
+    fn it_parses_resume_packets() {
+        let cases = &[
+            (&b"c"[..], None, None),
+            (b"c1000", None, Some(0x1000)),
+            (b"C05", Some(5), None),
+            (b"C05;1000", Some(5), Some(0x1000)),
+        ];
+        for &(data, signal, addr) in cases {
+            let packet = CheckedPacket::from_data(Kind::Packet, data.to_vec());
+            let cont = Continue::from_packet(&packet).unwrap();
+            assert_eq!(cont, Continue { signal, addr });
+            assert_eq!(cont.to_packet(), packet);
+
+            let mut data = data.to_vec();
+            data[0] = data[0] + b's' - b'c';
+            let packet = CheckedPacket::from_data(Kind::Packet, data);
+            let step = Step::from_packet(&packet).unwrap();
+            assert_eq!(step, Step { signal, addr });
+            assert_eq!(step.to_packet(), packet);
+        }
+    }
+    #[test]
+    fn it_rejects_malformed_resume_packets() {
+        for data in &[&b""[..], b"s", b"cxyz", b"C", b"C05;", b"C05;zz", b"C123"] {
+            let packet = CheckedPacket::from_data(Kind::Packet, data.to_vec());
+            assert!(Continue::from_packet(&packet).is_err(), "{:?}", data);
+        }
+        let packet = CheckedPacket::from_data(Kind::Packet, b"c".to_vec());
+        assert!(Step::from_packet(&packet).is_err());
+    }
+    #[test]
+    fn it_parses_commands() {
+        let parse = |data: &[u8]| parse(&CheckedPacket::from_data(Kind::Packet, data.to_vec()));
+
+        assert_eq!(parse(b"!").unwrap(), Some(Command::ExtendedMode));
+        assert_eq!(parse(b"?").unwrap(), Some(Command::HaltReason));
+        assert_eq!(parse(b"??").unwrap(), None);
+        assert_eq!(parse(b"R00").unwrap(), Some(Command::Restart { arg: 0 }));
+        assert_eq!(parse(b"Rff").unwrap(), Some(Command::Restart { arg: 0xff }));
+        assert!(parse(b"R").is_err());
+        assert!(parse(b"Rxx").is_err());
+        assert_eq!(parse(b"!!").unwrap(), None);
+        assert_eq!(parse(b"D").unwrap(), Some(Command::Detach { pid: None }));
+        assert_eq!(
+            parse(b"D;1").unwrap(),
+            Some(Command::Detach { pid: Some(1) })
+        );
+        assert_eq!(
+            parse(b"D;1f").unwrap(),
+            Some(Command::Detach { pid: Some(0x1f) })
+        );
+        assert!(parse(b"D;").is_err());
+        assert!(parse(b"D;xx").is_err());
+        assert_eq!(parse(b"Dx").unwrap(), None);
+        assert_eq!(parse(b"qC").unwrap(), Some(Command::CurrentThread));
+        assert_eq!(parse(b"qCRC:0,4").unwrap(), None);
+        assert_eq!(
+            parse(b"qAttached").unwrap(),
+            Some(Command::QueryAttached { pid: None })
+        );
+        assert_eq!(
+            parse(b"qAttached:2a").unwrap(),
+            Some(Command::QueryAttached { pid: Some(0x2a) })
+        );
+        assert!(parse(b"qAttached:").is_err());
+        assert_eq!(
+            parse(b"T1").unwrap(),
+            Some(Command::ThreadAlive {
+                thread: ThreadId::thread(1)
+            })
+        );
+        assert_eq!(
+            parse(b"Tp1.2").unwrap(),
+            Some(Command::ThreadAlive {
+                thread: ThreadId {
+                    pid: Some(Id::Id(1)),
+                    tid: Id::Id(2),
+                }
+            })
+        );
+        assert_eq!(
+            parse(b"QNonStop:1").unwrap(),
+            Some(Command::SetNonStop { enable: true })
+        );
+        assert_eq!(
+            parse(b"QNonStop:0").unwrap(),
+            Some(Command::SetNonStop { enable: false })
+        );
+        assert!(parse(b"QNonStop:2").is_err());
+        assert!(parse(b"QNonStop:").is_err());
+        assert_eq!(parse(b"T").unwrap(), None);
+        assert_eq!(parse(b"T05thread:1;").unwrap(), None);
+        assert_eq!(
+            parse(b"qTStatus").unwrap(),
+            Some(Command::Tracepoint(b"Status".to_vec()))
+        );
+        assert_eq!(
+            parse(b"QTinit").unwrap(),
+            Some(Command::Tracepoint(b"init".to_vec()))
+        );
+        assert_eq!(parse(b"qThreadExtraInfo,1").unwrap(), None);
+        assert_eq!(parse(b"QThreadEvents:1").unwrap(), None);
+        assert_eq!(parse(b"qTLSAddr:1,0,0").unwrap(), None);
+        assert_eq!(parse(b"g").unwrap(), None);
+        assert_eq!(parse(b"").unwrap(), None);
+    }
+    #[test]
+    fn it_parses_kill_packets() {
+        let packet = CheckedPacket::from_data(Kind::Packet, b"vKill;1".to_vec());
+        let kill = Kill::from_packet(&packet).unwrap();
+        assert_eq!(kill, Kill { pid: 1 });
+        assert_eq!(kill.to_packet(), packet);
+        assert_eq!(Kill { pid: 0x2a }.to_packet().data, b"vKill;2a");
+
+        for data in &[&b"vKill"[..], b"vKill;", b"vKill;xx", b"vKill;+1", b"k"] {
+            let packet = CheckedPacket::from_data(Kind::Packet, data.to_vec());
+            assert!(Kill::from_packet(&packet).is_err(), "{:?}", data);
+        }
+    }
+    #[test]
+    fn it_parses_signal_lists() {
+        let packet = CheckedPacket::from_data(Kind::Packet, b"QPassSignals:e;a".to_vec());
+        let list = SignalList::from_packet(&packet).unwrap();
+        assert_eq!(
+            list,
+            SignalList {
+                program: false,
+                signals: vec![0x0e, 0x0a],
+            }
+        );
+        assert_eq!(list.to_packet(), packet);
+
+        let packet = CheckedPacket::from_data(Kind::Packet, b"QPassSignals:".to_vec());
+        let list = SignalList::from_packet(&packet).unwrap();
+        assert!(list.signals.is_empty());
+        assert_eq!(list.to_packet(), packet);
+
+        let list = SignalList {
+            program: true,
+            signals: vec![0x11, 2],
+        };
+        assert_eq!(list.to_packet().data, b"QProgramSignals:11;2");
+        assert_eq!(SignalList::from_packet(&list.to_packet()).unwrap(), list);
+
+        for data in &[
+            &b"QPassSignals"[..],
+            b"QPassSignals:e;",
+            b"QPassSignals:100",
+            b"QPassSignals:+1",
+            b"QProgramSignals:x",
+        ] {
+            let packet = CheckedPacket::from_data(Kind::Packet, data.to_vec());
+            assert!(SignalList::from_packet(&packet).is_err(), "{:?}", data);
+        }
+    }
+    #[test]
+    fn it_converts_register_values() {
+        assert_eq!(reg_to_hex_le(0xdeadbeef, 4), b"efbeadde");
+        assert_eq!(reg_to_hex_be(0xdeadbeef, 4), b"deadbeef");
+        assert_eq!(reg_to_hex_le(0x1234, 8), b"3412000000000000");
+        assert_eq!(reg_to_hex_be(0x1234, 8), b"0000000000001234");
+        assert_eq!(reg_to_hex_le(0x1234, 1), b"34");
+
+        assert_eq!(reg_from_hex_le(b"efbeadde").unwrap(), 0xdeadbeef);
+        assert_eq!(reg_from_hex_be(b"deadbeef").unwrap(), 0xdeadbeef);
+        assert_eq!(reg_from_hex_le(b"3412000000000000").unwrap(), 0x1234);
+        assert_eq!(reg_from_hex_be(b"0000000000001234").unwrap(), 0x1234);
+        assert_eq!(reg_from_hex_le(b"").unwrap(), 0);
+
+        assert!(reg_from_hex_le(b"000000000000000000").is_err());
+        assert!(reg_from_hex_be(b"efbeadd").is_err());
+    }
+    #[test]
+    fn it_parses_register_file_packets() {
+        let packet = CheckedPacket::from_data(Kind::Packet, b"g".to_vec());
+        assert_eq!(ReadRegisters::from_packet(&packet).unwrap(), ReadRegisters);
+        assert_eq!(ReadRegisters.to_packet(), packet);
+        let packet = CheckedPacket::from_data(Kind::Packet, b"g0".to_vec());
+        assert!(ReadRegisters::from_packet(&packet).is_err());
+
+        let mut registers = reg_to_hex_le(0xdeadbeef, 4);
+        registers.extend_from_slice(&reg_to_hex_le(0x1000, 4));
+        registers.insert(0, b'G');
+        let packet = CheckedPacket::from_data(Kind::Packet, registers);
+        let raw = WriteRegisters::from_packet(&packet).unwrap();
+        assert_eq!(raw, [0xef, 0xbe, 0xad, 0xde, 0x00, 0x10, 0x00, 0x00]);
+        assert_eq!(WriteRegisters::to_packet(&raw), packet);
+
+        for data in &[&b"G0"[..], b"Gzz", b"g00"] {
+            let packet = CheckedPacket::from_data(Kind::Packet, data.to_vec());
+            assert!(WriteRegisters::from_packet(&packet).is_err(), "{:?}", data);
+        }
+    }
+    #[test]
+    fn it_parses_register_replies() {
+        let reply = RegisterReply::to_packet(&[0xe0, 0x01, 0xff]);
+        assert_eq!(reply.data, b"e001ff");
+        assert_eq!(RegisterReply::parse(&reply).unwrap(), [0xe0, 0x01, 0xff]);
+        let packet = CheckedPacket::from_data(Kind::Packet, b"E0".to_vec());
+        assert_eq!(RegisterReply::parse(&packet).unwrap(), [0xe0]);
+
+        match RegisterReply::parse(&error_reply(1)) {
+            Err(Error::Malformed(data)) => assert_eq!(data, b"E01"),
+            result => panic!("Expected error Malformed, got {:?}", result),
+        }
+        let packet = CheckedPacket::from_data(Kind::Packet, b"E01f".to_vec());
+        assert_eq!(RegisterReply::parse(&packet).unwrap(), [0xe0, 0x1f]);
+    }
+    #[test]
+    fn it_chunks_replies() {
+        let data = b"0123}#$*abc}}}}d*e";
+        for max_size in 7..30 {
+            let packets = chunk_reply(data, max_size).unwrap();
+            let mut joined = Vec::new();
+            for (i, packet) in packets.iter().enumerate() {
+                let last = i == packets.len() - 1;
+                assert!(packet.encoded_len() <= max_size, "{}", packet.display());
+                assert_eq!(packet.data[0], if last { b'l' } else { b'm' });
+                joined.extend_from_slice(&packet.data[1..]);
+            }
+            assert_eq!(joined, &data[..]);
+
+            let packets = chunk_data(data, max_size).unwrap();
+            assert!(packets.iter().all(|p| p.encoded_len() <= max_size));
+            let joined: Vec<u8> = packets.iter().flat_map(|p| p.data.clone()).collect();
+            assert_eq!(joined, &data[..]);
+        }
+        // There's room for 4 encoded bytes, and the escaped } takes 2
+        let packets = chunk_reply(b"012}", 9).unwrap();
+        assert_eq!(packets[0].data, b"m012");
+        assert_eq!(packets[1].data, b"l}");
+        assert_eq!(
+            chunk_reply(b"", 10).unwrap(),
+            vec![CheckedPacket::from_data(Kind::Packet, b"l".to_vec())]
+        );
+        assert_eq!(chunk_data(b"}", 6).unwrap()[0].data, b"}");
+        match chunk_reply(b"0", 6) {
+            Err(Error::PacketTooLarge { len: 7, max: 6, .. }) => (),
+            result => panic!("Expected error PacketTooLarge, got {:?}", result),
+        }
+        match chunk_data(b"", 0) {
+            Err(Error::PacketTooLarge { len: 6, max: 0, .. }) => (),
+            result => panic!("Expected error PacketTooLarge, got {:?}", result),
+        }
+    }
+    #[test]
+    fn it_parses_binary_writes() {
+        let packet = CheckedPacket::from_data(Kind::Packet, b"X20,3:a#\x00".to_vec());
+        let write = BinaryWrite::from_packet(&packet).unwrap();
+        assert_eq!(
+            write,
+            BinaryWrite {
+                addr: 0x20,
+                data: b"a#\x00".to_vec()
+            }
+        );
+        assert_eq!(write.to_packet(), packet);
+
+        for data in &[&b"X20,4:a#\x00"[..], b"X20:", b"X,0:", b"M20,1:00"] {
+            let packet = CheckedPacket::from_data(Kind::Packet, data.to_vec());
+            assert!(BinaryWrite::from_packet(&packet).is_err(), "{:?}", data);
+        }
+    }
+    #[test]
+    fn it_coalesces_writes() {
+        let write = |addr, byte| BinaryWrite {
+            addr,
+            data: vec![byte; 16],
+        };
+        let writes = [write(0x1020, 3), write(0x1000, 1), write(0x1010, 2)];
+
+        let merged = coalesce_writes(&writes, 64).unwrap();
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].addr, 0x1000);
+        assert_eq!(merged[0].data.len(), 48);
+        assert_eq!(
+            merged[0].data[..],
+            [&[1; 16][..], &[2; 16], &[3; 16]].concat()[..]
+        );
+
+        let merged = coalesce_writes(&writes, 32).unwrap();
+        assert_eq!(
+            merged.iter().map(|write| write.addr).collect::<Vec<_>>(),
+            [0x1000, 0x1020]
+        );
+        assert_eq!(
+            coalesce_writes(&[write(0, 1), write(0x20, 2)], 64)
+                .unwrap()
+                .len(),
+            2
+        );
+
+        match coalesce_writes(&[write(0, 1), write(8, 2)], 64) {
+            Err(Error::Malformed(data)) => assert!(data.starts_with(b"X8,10:")),
+            result => panic!("Expected error Malformed, got {:?}", result),
+        }
+    }
+    #[test]
+    fn it_builds_file_io_replies() {
+        let attachment = b"\0\x01#$}*\xfe\xff01234567";
+        let reply = FileIoReply::with_attachment(attachment.len() as i64, attachment);
+        assert_eq!(reply.data, [&b"F10;"[..], attachment].concat());
+
+        let mut encoded = Vec::new();
+        reply.encode(&mut encoded).unwrap();
+        assert_eq!(
+            encoded,
+            &b"$F10;\0\x01}\x03}\x04}]}\x0a\xfe\xff01234567#6a"[..]
+        );
+
+        assert_eq!(FileIoReply::success(0).data, b"F0");
+        assert_eq!(FileIoReply::success(0x1ff).data, b"F1ff");
+        assert_eq!(FileIoReply::success(-2).data, b"F-2");
+        assert_eq!(FileIoReply::success(i64::MIN).data, b"F-8000000000000000");
+        assert_eq!(FileIoReply::error(2).data, b"F-1,2");
+        assert_eq!(FileIoReply::error(9999).data, b"F-1,270f");
+    }
+    #[test]
+    fn it_parses_breakpoint_packets() {
+        let packet = CheckedPacket::from_data(Kind::Packet, b"Z0,1000,4".to_vec());
+        let bp = Breakpoint::from_packet(&packet).unwrap();
+        assert_eq!(
+            bp,
+            Breakpoint {
+                set: true,
+                bp_type: BpType::Software,
+                addr: 0x1000,
+                kind: 4,
+                tail: Vec::new(),
+            }
+        );
+        assert_eq!(bp.to_packet(), packet);
+
+        let packet = CheckedPacket::from_data(Kind::Packet, b"z2,2000,8".to_vec());
+        let bp = Breakpoint::from_packet(&packet).unwrap();
+        assert_eq!(
+            bp,
+            Breakpoint {
+                set: false,
+                bp_type: BpType::WriteWatchpoint,
+                addr: 0x2000,
+                kind: 8,
+                tail: Vec::new(),
+            }
+        );
+        assert_eq!(bp.to_packet(), packet);
+
+        let packet = CheckedPacket::from_data(Kind::Packet, b"Z1,1000,2;X3,220027".to_vec());
+        let bp = Breakpoint::from_packet(&packet).unwrap();
+        assert_eq!(bp.bp_type, BpType::Hardware);
+        assert_eq!(bp.tail, b"X3,220027");
+        assert_eq!(bp.to_packet(), packet);
+    }
+    #[test]
+    fn it_rejects_malformed_breakpoint_packets() {
+        for data in &[
+            &b"Z0,1000"[..],
+            b"Z0,1000,4,1",
+            b"Z5,1000,4",
+            b"Z0,zz,4",
+            b"c0,1000,4",
+            b"Z",
+        ] {
+            let packet = CheckedPacket::from_data(Kind::Packet, data.to_vec());
+            assert!(Breakpoint::from_packet(&packet).is_err(), "{:?}", data);
+        }
+    }
+    #[test]
+    fn it_recognizes_halt_reason_queries() {
+        let packet = crate::parser::parse_all(b"$?#3f").unwrap().remove(0);
+        let packet = packet.check().unwrap();
+        assert_eq!(parse(&packet).unwrap(), Some(Command::HaltReason));
+
+        assert_eq!(StopReply::signal(5).data, b"S05");
+        assert_eq!(StopReply::exited(0).data, b"W00");
+        assert_eq!(StopReply::terminated(9).data, b"X09");
+    }
+    #[test]
+    fn it_parses_thread_ids() {
+        let cases = &[
+            (&b"1f"[..], ThreadId::thread(0x1f)),
+            (
+                b"-1",
+                ThreadId {
+                    pid: None,
+                    tid: Id::All,
+                },
+            ),
+            (
+                b"0",
+                ThreadId {
+                    pid: None,
+                    tid: Id::Any,
+                },
+            ),
+            (
+                b"p2.3",
+                ThreadId {
+                    pid: Some(Id::Id(2)),
+                    tid: Id::Id(3),
+                },
+            ),
+            (
+                b"p-1.-1",
+                ThreadId {
+                    pid: Some(Id::All),
+                    tid: Id::All,
+                },
+            ),
+        ];
+        for &(bytes, id) in cases {
+            assert_eq!(ThreadId::parse(bytes).unwrap(), id, "{:?}", bytes);
+            assert_eq!(id.encode(), bytes);
+        }
+        assert_eq!(
+            ThreadId::parse(b"p2").unwrap(),
+            ThreadId {
+                pid: Some(Id::Id(2)),
+                tid: Id::All
+            }
+        );
+        for bytes in &[&b""[..], b"p", b"p.1", b"zz", b"-2"] {
+            assert!(ThreadId::parse(bytes).is_err(), "{:?}", bytes);
+        }
+    }
+    #[test]
+    fn it_enumerates_threads() {
+        let threads = [ThreadId::thread(1), ThreadId::thread(2)];
+        // Room for one thread per reply
+        let mut replies = thread_info_replies(&threads, 6).into_iter();
+
+        let mut exchange = |data: &[u8]| {
+            let packet = CheckedPacket::from_data(Kind::Packet, data.to_vec());
+            let query = ThreadInfo::from_packet(&packet).unwrap();
+            assert_eq!(query.to_packet(), packet);
+            (query, replies.next().unwrap().data.clone())
+        };
+        assert_eq!(
+            exchange(b"qfThreadInfo"),
+            (ThreadInfo::First, b"m1".to_vec())
+        );
+        assert_eq!(
+            exchange(b"qsThreadInfo"),
+            (ThreadInfo::Subsequent, b"m2".to_vec())
+        );
+        assert_eq!(
+            exchange(b"qsThreadInfo"),
+            (ThreadInfo::Subsequent, b"l".to_vec())
+        );
+
+        assert_eq!(thread_info_reply(&threads, false).data, b"m1,2");
+        assert_eq!(thread_info_replies(&threads, 100).len(), 2);
+        assert_eq!(
+            thread_info_replies(&[], 100),
+            vec![thread_info_reply(&[], true)]
+        );
+        let packet = CheckedPacket::from_data(Kind::Packet, b"qC".to_vec());
+        assert!(ThreadInfo::from_packet(&packet).is_err());
+    }
+    #[test]
+    fn it_builds_handshake_replies() {
+        let thread = ThreadId {
+            pid: Some(Id::Id(1)),
+            tid: Id::Id(2),
+        };
+        assert_eq!(current_thread_reply(thread).data, b"QCp1.2");
+        assert_eq!(current_thread_reply(ThreadId::thread(0x1f)).data, b"QC1f");
+        assert_eq!(attached_reply(true).data, b"1");
+        assert_eq!(attached_reply(false).data, b"0");
+    }
+}
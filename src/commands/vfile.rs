@@ -0,0 +1,326 @@
+//! The `vFile` packets of GDB's host I/O, through which GDB accesses
+//! files on the target's filesystem.
+//!
+//! Binary data, as written by `pwrite` or attached to a `pread` reply,
+//! is kept as raw bytes: escaping it is taken care of when the packet
+//! is encoded, the same way as for `X` packets.
+
+use super::{from_hex, hex_number, to_hex};
+use crate::{
+    packet::{CheckedPacket, Kind},
+    Error,
+};
+
+/// Split the parameters of a `vFile:<operation>:` packet, returning
+/// `None` if the packet is of another operation. At most `n` fields
+/// are split off, so the last one may contain commas.
+fn params<'a>(packet: &'a CheckedPacket, operation: &str, n: usize) -> Option<Vec<&'a [u8]>> {
+    let rest = packet.data.strip_prefix(b"vFile:")?;
+    let rest = rest.strip_prefix(operation.as_bytes())?;
+    let rest = rest.strip_prefix(b":")?;
+    Some(rest.splitn(n, |&b| b == b',').collect())
+}
+fn fields<'a>(
+    packet: &'a CheckedPacket,
+    operation: &str,
+    n: usize,
+) -> Result<Vec<&'a [u8]>, Error> {
+    match params(packet, operation, n) {
+        Some(fields) if fields.len() == n => Ok(fields),
+        _ => Err(Error::Malformed(packet.data.clone())),
+    }
+}
+
+/// The `vFile:open: filename, flags, mode` packet. The flags and mode
+/// use GDB's own values, as listed in the File-I/O section of the
+/// specification, rather than those of the host.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Open {
+    pub filename: Vec<u8>,
+    pub flags: u32,
+    pub mode: u32,
+}
+impl Open {
+    pub fn from_packet(packet: &CheckedPacket) -> Result<Self, Error> {
+        let fields = fields(packet, "open", 3)?;
+        Ok(Self {
+            filename: from_hex(fields[0])?,
+            flags: hex_number(fields[1], u32::from_str_radix)?,
+            mode: hex_number(fields[2], u32::from_str_radix)?,
+        })
+    }
+    pub fn to_packet(&self) -> CheckedPacket {
+        let mut data = b"vFile:open:".to_vec();
+        data.extend_from_slice(&to_hex(&self.filename));
+        data.extend_from_slice(format!(",{:x},{:x}", self.flags, self.mode).as_bytes());
+        CheckedPacket::from_data(Kind::Packet, data)
+    }
+}
+
+/// The `vFile:pread: fd, count, offset` packet, reading up to `count`
+/// bytes at `offset`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pread {
+    pub fd: u32,
+    pub count: u64,
+    pub offset: u64,
+}
+impl Pread {
+    pub fn from_packet(packet: &CheckedPacket) -> Result<Self, Error> {
+        let fields = fields(packet, "pread", 3)?;
+        Ok(Self {
+            fd: hex_number(fields[0], u32::from_str_radix)?,
+            count: hex_number(fields[1], u64::from_str_radix)?,
+            offset: hex_number(fields[2], u64::from_str_radix)?,
+        })
+    }
+    pub fn to_packet(&self) -> CheckedPacket {
+        let data = format!(
+            "vFile:pread:{:x},{:x},{:x}",
+            self.fd, self.count, self.offset
+        );
+        CheckedPacket::from_data(Kind::Packet, data.into_bytes())
+    }
+}
+
+/// The `vFile:pwrite: fd, offset, data` packet, writing the binary
+/// `data` at `offset`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pwrite {
+    pub fd: u32,
+    pub offset: u64,
+    pub data: Vec<u8>,
+}
+impl Pwrite {
+    pub fn from_packet(packet: &CheckedPacket) -> Result<Self, Error> {
+        let fields = fields(packet, "pwrite", 3)?;
+        Ok(Self {
+            fd: hex_number(fields[0], u32::from_str_radix)?,
+            offset: hex_number(fields[1], u64::from_str_radix)?,
+            data: fields[2].to_vec(),
+        })
+    }
+    pub fn to_packet(&self) -> CheckedPacket {
+        let mut data = format!("vFile:pwrite:{:x},{:x},", self.fd, self.offset).into_bytes();
+        data.extend_from_slice(&self.data);
+        CheckedPacket::from_data(Kind::Packet, data)
+    }
+}
+
+/// The `vFile:close: fd` packet.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Close {
+    pub fd: u32,
+}
+impl Close {
+    pub fn from_packet(packet: &CheckedPacket) -> Result<Self, Error> {
+        let fields = fields(packet, "close", 1)?;
+        Ok(Self {
+            fd: hex_number(fields[0], u32::from_str_radix)?,
+        })
+    }
+    pub fn to_packet(&self) -> CheckedPacket {
+        let data = format!("vFile:close:{:x}", self.fd);
+        CheckedPacket::from_data(Kind::Packet, data.into_bytes())
+    }
+}
+
+/// A host I/O request, recognized by `parse`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "unstable", non_exhaustive)]
+pub enum Request {
+    Open(Open),
+    Pread(Pread),
+    Pwrite(Pwrite),
+    Close(Close),
+}
+
+/// Recognize a `Request`. Returns `None` for any other packet,
+/// including `vFile` operations not supported by this module.
+pub fn parse(packet: &CheckedPacket) -> Result<Option<Request>, Error> {
+    let data = &packet.data[..];
+    let operation = match data.strip_prefix(b"vFile:") {
+        Some(rest) => &rest[..memchr::memchr(b':', rest).unwrap_or(rest.len())],
+        None => return Ok(None),
+    };
+    Ok(Some(match operation {
+        b"open" => Request::Open(Open::from_packet(packet)?),
+        b"pread" => Request::Pread(Pread::from_packet(packet)?),
+        b"pwrite" => Request::Pwrite(Pwrite::from_packet(packet)?),
+        b"close" => Request::Close(Close::from_packet(packet)?),
+        _ => return Ok(None),
+    }))
+}
+
+/// The `F result[,errno][;attachment]` reply to a host I/O request.
+/// A `result` of -1 reports an error, with the GDB error number in
+/// `errno`. Replies to `pread` attach the binary data read, with
+/// `result` being its length.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Reply {
+    pub result: i64,
+    pub errno: Option<u32>,
+    pub attachment: Option<Vec<u8>>,
+}
+impl Reply {
+    /// A successful reply without attachment.
+    pub fn ok(result: i64) -> Self {
+        Self {
+            result,
+            errno: None,
+            attachment: None,
+        }
+    }
+    /// A failed reply, reporting the GDB error number `errno`.
+    pub fn error(errno: u32) -> Self {
+        Self {
+            result: -1,
+            errno: Some(errno),
+            attachment: None,
+        }
+    }
+    /// A reply to `pread`, carrying the data read.
+    pub fn data(data: impl Into<Vec<u8>>) -> Self {
+        let data = data.into();
+        Self {
+            result: data.len() as i64,
+            errno: None,
+            attachment: Some(data),
+        }
+    }
+
+    pub fn from_packet(packet: &CheckedPacket) -> Result<Self, Error> {
+        let malformed = || Error::Malformed(packet.data.clone());
+
+        let rest = packet.data.strip_prefix(b"F").ok_or_else(malformed)?;
+        let (fields, attachment) = match memchr::memchr(b';', rest) {
+            Some(semicolon) => (&rest[..semicolon], Some(rest[semicolon + 1..].to_vec())),
+            None => (rest, None),
+        };
+        let (result, errno) = match memchr::memchr(b',', fields) {
+            Some(comma) => (&fields[..comma], Some(&fields[comma + 1..])),
+            None => (fields, None),
+        };
+        let result = match result.strip_prefix(b"-") {
+            Some(magnitude) => match hex_number(magnitude, u64::from_str_radix)? {
+                // Down to i64::MIN, whose magnitude doesn't fit an i64
+                magnitude if magnitude <= 1 << 63 => (magnitude as i64).wrapping_neg(),
+                _ => return Err(malformed()),
+            },
+            None => hex_number(result, i64::from_str_radix)?,
+        };
+        Ok(Self {
+            result,
+            errno: errno
+                .map(|errno| hex_number(errno, u32::from_str_radix))
+                .transpose()?,
+            attachment,
+        })
+    }
+    pub fn to_packet(&self) -> CheckedPacket {
+        let mut data = if self.result < 0 {
+            format!("F-{:x}", self.result.unsigned_abs())
+        } else {
+            format!("F{:x}", self.result)
+        };
+        if let Some(errno) = self.errno {
+            data.push_str(&format!(",{:x}", errno));
+        }
+        let mut data = data.into_bytes();
+        if let Some(attachment) = &self.attachment {
+            data.push(b';');
+            data.extend_from_slice(attachment);
+        }
+        CheckedPacket::from_data(Kind::Packet, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::read_packet;
+    use crate::parser::Parser;
+
+    #[test]
+    fn it_parses_open_requests() {
+        // open("/etc/hosts", O_RDONLY, 0)
+        let packet = CheckedPacket::from_data(
+            Kind::Packet,
+            b"vFile:open:2f6574632f686f737473,0,0".to_vec(),
+        );
+        let open = Open {
+            filename: b"/etc/hosts".to_vec(),
+            flags: 0,
+            mode: 0,
+        };
+        assert_eq!(parse(&packet).unwrap(), Some(Request::Open(open.clone())));
+        assert_eq!(open.to_packet(), packet);
+
+        for data in &[
+            &b"vFile:open:2f,0"[..],
+            b"vFile:open:2f0,0,0",
+            b"vFile:open2f,0,0",
+        ] {
+            let packet = CheckedPacket::from_data(Kind::Packet, data.to_vec());
+            assert!(Open::from_packet(&packet).is_err(), "{:?}", data);
+        }
+        let packet = CheckedPacket::from_data(Kind::Packet, b"vFile:setfs:0".to_vec());
+        assert_eq!(parse(&packet).unwrap(), None);
+    }
+    #[test]
+    fn it_parses_other_requests() {
+        let parse = |data: &[u8]| parse(&CheckedPacket::from_data(Kind::Packet, data.to_vec()));
+
+        assert_eq!(
+            parse(b"vFile:pread:3,100,2000").unwrap(),
+            Some(Request::Pread(Pread {
+                fd: 3,
+                count: 0x100,
+                offset: 0x2000,
+            }))
+        );
+        assert_eq!(
+            parse(b"vFile:pwrite:3,10,a,b").unwrap(),
+            Some(Request::Pwrite(Pwrite {
+                fd: 3,
+                offset: 0x10,
+                data: b"a,b".to_vec(),
+            }))
+        );
+        assert_eq!(
+            parse(b"vFile:close:3").unwrap(),
+            Some(Request::Close(Close { fd: 3 }))
+        );
+        assert!(parse(b"vFile:close:3,4").is_err());
+    }
+    #[test]
+    fn it_round_trips_binary_pread_replies() {
+        let contents = b"\x00\x01#$}*\x7f\xff".to_vec();
+        let reply = Reply::data(contents.clone());
+
+        let mut encoded = Vec::new();
+        reply.to_packet().encode(&mut encoded).unwrap();
+        assert!(encoded.starts_with(b"$F8;\x00\x01}\x03}\x04}]}\x0a"));
+
+        let mut input = &encoded[..];
+        let packet = read_packet(&mut input, &mut Parser::default())
+            .unwrap()
+            .unwrap();
+        let parsed = Reply::from_packet(&packet).unwrap();
+        assert_eq!(parsed.result, 8);
+        assert_eq!(parsed.attachment, Some(contents));
+    }
+    #[test]
+    fn it_builds_replies() {
+        assert_eq!(Reply::ok(3).to_packet().data, b"F3");
+        assert_eq!(Reply::error(2).to_packet().data, b"F-1,2");
+        let packet = CheckedPacket::from_data(Kind::Packet, b"F-1,2".to_vec());
+        assert_eq!(Reply::from_packet(&packet).unwrap(), Reply::error(2));
+        let packet = CheckedPacket::from_data(Kind::Packet, b"F-8000000000000000".to_vec());
+        assert_eq!(Reply::from_packet(&packet).unwrap().result, i64::MIN);
+        for data in &[&b"OK"[..], b"F+1", b"F--1", b"F-8000000000000001"] {
+            let packet = CheckedPacket::from_data(Kind::Packet, data.to_vec());
+            assert!(Reply::from_packet(&packet).is_err(), "{:?}", data);
+        }
+    }
+}
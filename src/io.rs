@@ -1,27 +1,238 @@
 use crate::{
-    packet::{CheckedPacket, Kind},
+    packet::{CheckedPacket, Checksum, Gdb8BitSum, Kind, UncheckedPacket},
     parser::Parser,
     Error,
 };
 
 use std::{
+    cmp,
+    collections::VecDeque,
     io::{prelude::*, BufReader},
     mem,
     net::{TcpListener, TcpStream, ToSocketAddrs},
 };
 
+/// The capacity of the read buffer of servers created by `listen` and
+/// `from_stream`. Use `from_stream_with_capacity` or `with_capacity`
+/// to pick another size.
 pub const BUF_SIZE: usize = 8 * 1024;
 
-pub struct GdbServer<R, W>
+/// Something received from the peer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    Packet(CheckedPacket),
+    /// A bare `0x03` byte outside of a packet, which GDB sends to
+    /// interrupt the running target (Ctrl-C). It is not acknowledged.
+    Interrupt,
+}
+
+/// Which way bytes passed to a trace hook travelled, see
+/// `GdbServer::set_trace`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// Read from the peer.
+    In,
+    /// Written to the peer.
+    Out,
+}
+
+type TraceHook = Box<dyn FnMut(Direction, &[u8]) + Send>;
+
+/// A received packet, as seen by an `AckPolicy`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheckResult<'a> {
+    /// A packet with a matching checksum, or any packet if checksum
+    /// verification is disabled.
+    Valid(&'a CheckedPacket),
+    Invalid(&'a UncheckedPacket),
+}
+
+/// What to reply to a received packet, see `AckPolicy`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "unstable", non_exhaustive)]
+pub enum AckAction {
+    /// Acknowledge the packet. An invalid packet is then returned as
+    /// `Error::ChecksumMismatch`.
+    SendPositive,
+    /// Ask for the packet to be retransmitted, and wait for it.
+    SendNegative,
+    /// Reply nothing. An invalid packet is then returned as
+    /// `Error::ChecksumMismatch`.
+    None,
+}
+
+/// Decides how a server acknowledges the packets it receives, see
+/// `GdbServer::set_ack_policy`.
+pub trait AckPolicy: Send {
+    fn on_packet(&mut self, result: CheckResult) -> AckAction;
+}
+
+/// The policy of the protocol: acknowledge valid packets, deny invalid
+/// ones, and never reply to notifications.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DefaultAckPolicy;
+impl AckPolicy for DefaultAckPolicy {
+    fn on_packet(&mut self, result: CheckResult) -> AckAction {
+        match result {
+            CheckResult::Valid(packet) if packet.kind == Kind::Packet => AckAction::SendPositive,
+            CheckResult::Invalid(packet) if packet.kind == Kind::Packet => AckAction::SendNegative,
+            _ => AckAction::None,
+        }
+    }
+}
+
+/// Reads and checksum-verifies the next packet from `reader`, without
+/// writing any acknowledgment. Returns `None` on EOF, or
+/// `Error::UnexpectedEof` if the input ended in the middle of a
+/// packet, and `Error::ChecksumMismatch` if the packet was
+/// corrupted. This is useful for analyzing captures where there is no
+/// peer to respond to.
+pub fn read_packet<R, C>(
+    reader: &mut R,
+    parser: &mut Parser<C>,
+) -> Result<Option<CheckedPacket>, Error>
+where
+    R: BufRead,
+    C: Checksum,
+{
+    read_event(reader, parser, false, None, &mut |_, _| ()).map(|event| match event {
+        Some(Event::Packet(packet)) => Some(packet),
+        Some(Event::Interrupt) => unreachable!("interrupts are not reported"),
+        None => None,
+    })
+}
+fn read_event<R, C>(
+    reader: &mut R,
+    parser: &mut Parser<C>,
+    interrupts: bool,
+    limit: Option<usize>,
+    trace: &mut dyn FnMut(Direction, &[u8]),
+) -> Result<Option<Event>, Error>
+where
+    R: BufRead,
+    C: Checksum,
+{
+    loop {
+        // Checked on every read, so garbage can't be streamed forever
+        if matches!(limit, Some(limit) if parser.bytes_consumed() > limit) {
+            break Err(Error::SessionLimitExceeded);
+        }
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            parser.finish()?;
+            break Ok(None);
+        }
+
+        if interrupts && parser.is_idle() {
+            let start = memchr::memchr2(b'$', b'%', buf).unwrap_or(buf.len());
+            if let Some(pos) = memchr::memchr(0x03, &buf[..start]) {
+                trace(Direction::In, &buf[..pos + 1]);
+                reader.consume(pos + 1);
+                parser.skip(pos + 1);
+                break Ok(Some(Event::Interrupt));
+            }
+        }
+
+        // println!("{:?}", std::str::from_utf8(buf));
+        let before = parser.bytes_consumed();
+        let (read, packet) = match parser.feed_checked(buf) {
+            Ok(result) => result,
+            Err(err) => {
+                // Skip what the parser did process, so a discarded
+                // packet isn't parsed again
+                let read = parser.bytes_consumed() - before;
+                trace(Direction::In, &buf[..read]);
+                reader.consume(read);
+                return Err(err);
+            }
+        };
+        trace(Direction::In, &buf[..read]);
+        reader.consume(read);
+
+        match packet {
+            Some(Ok(checked)) => break Ok(Some(Event::Packet(checked))),
+            Some(Err(unchecked)) => break Err(Error::ChecksumMismatch(unchecked)),
+            None => (),
+        }
+    }
+}
+
+pub struct GdbServer<R, W, C = Gdb8BitSum>
 where
     R: BufRead,
     W: Write,
 {
     pub reader: R,
     pub writer: W,
-    parser: Parser,
+    parser: Parser<C>,
+    pending_stops: VecDeque<Vec<u8>>,
+    pending_packets: VecDeque<CheckedPacket>,
+    max_send_size: Option<usize>,
+    verify_checksums: bool,
+    verify_notifications: bool,
+    coalesce_ack: bool,
+    pending_ack: bool,
+    unsupported_replies: usize,
+    disconnected: bool,
+    trace: Option<TraceHook>,
+    ack: u8,
+    nack: u8,
+    max_retransmits: usize,
+    ack_policy: Box<dyn AckPolicy>,
+    non_stop: bool,
+    dedup: bool,
+    last_received: Option<CheckedPacket>,
+    duplicates: usize,
+    checksum_mismatches: usize,
+    session_byte_limit: Option<usize>,
+}
+
+/// A connection to a peer, which a server can be created from with
+/// `GdbServer::serve`, regardless of what the connection is.
+pub trait Transport {
+    type Reader: BufRead;
+    type Writer: Write;
+
+    /// Describes the peer, such as its address, if anything is known.
+    fn peer(&self) -> Option<String>;
+    /// Splits the connection into the reader and writer of a server.
+    fn split(self) -> Result<(Self::Reader, Self::Writer), Error>;
+}
+/// Set up like `GdbServer::from_stream` does.
+impl Transport for TcpStream {
+    type Reader = BufReader<TcpStream>;
+    type Writer = TcpStream;
+
+    fn peer(&self) -> Option<String> {
+        self.peer_addr().ok().map(|addr| addr.to_string())
+    }
+    fn split(self) -> Result<(Self::Reader, Self::Writer), Error> {
+        self.set_nodelay(true)?;
+        Ok((BufReader::with_capacity(BUF_SIZE, self.try_clone()?), self))
+    }
+}
+/// A reader and a writer, such as those of `GdbServer::tester`.
+impl<R, W> Transport for (R, W)
+where
+    R: BufRead,
+    W: Write,
+{
+    type Reader = R;
+    type Writer = W;
+
+    fn peer(&self) -> Option<String> {
+        None
+    }
+    fn split(self) -> Result<(R, W), Error> {
+        Ok(self)
+    }
 }
 
+/// A server with its transport types erased, so servers over different
+/// transports (chosen at runtime, for example) have the same type. See
+/// `GdbServer::boxed`.
+pub type DynGdbServer = GdbServer<Box<dyn BufRead + Send>, Box<dyn Write + Send>>;
+
 impl GdbServer<BufReader<TcpStream>, TcpStream> {
     pub fn listen<A>(addr: A) -> Result<Self, Error>
     where
@@ -29,83 +240,579 @@ impl GdbServer<BufReader<TcpStream>, TcpStream> {
     {
         let listener = TcpListener::bind(addr)?;
 
-        let (writer, _addr) = listener.accept()?;
-        let reader = BufReader::new(writer.try_clone()?);
+        let (stream, _addr) = listener.accept()?;
+        Self::from_stream(stream)
+    }
+    /// Wraps an already connected stream, disabling Nagle's algorithm
+    /// as the protocol consists of many small packets.
+    ///
+    /// The reader is a `try_clone` of the stream, which means both
+    /// halves refer to the same socket: dropping one of them won't
+    /// close the connection, but a `shutdown` of the writer (through
+    /// `TcpStream::shutdown`) affects the reader as well.
+    pub fn from_stream(stream: TcpStream) -> Result<Self, Error> {
+        Self::serve(stream)
+    }
+    /// Like `from_stream`, but with a read buffer of the specified
+    /// capacity instead of `BUF_SIZE`.
+    pub fn from_stream_with_capacity(stream: TcpStream, capacity: usize) -> Result<Self, Error> {
+        stream.set_nodelay(true)?;
+        let reader = BufReader::with_capacity(capacity, stream.try_clone()?);
 
-        Ok(Self::new(reader, writer))
+        Ok(Self::new(reader, stream))
+    }
+}
+impl<T, W> GdbServer<BufReader<T>, W>
+where
+    T: Read,
+    W: Write,
+{
+    /// Creates a server reading through a `BufReader` of the specified
+    /// capacity. A larger buffer means fewer reads for big `m`/`X`
+    /// packets. The capacity does not limit the size of a packet,
+    /// since the parser keeps collecting data across reads; cap the
+    /// memory a packet may take with `Parser::set_max_len` instead,
+    /// through `parser_mut`.
+    pub fn with_capacity(capacity: usize, reader: T, writer: W) -> Self {
+        Self::new(BufReader::with_capacity(capacity, reader), writer)
+    }
+}
+impl<T, W, C> GdbServer<BufReader<T>, W, C>
+where
+    T: Read,
+    W: Write,
+    C: Checksum,
+{
+    /// Returns true if a packet has already been buffered completely,
+    /// meaning `next_packet` can return it without blocking. This
+    /// never reads from the underlying reader, and doesn't affect the
+    /// state of the parser, so an event loop can use it to drain all
+    /// ready packets before blocking again.
+    pub fn has_buffered_packet(&self) -> Result<bool, Error> {
+        if !self.pending_packets.is_empty() {
+            return Ok(true);
+        }
+        let mut parser = self.parser.clone();
+        let mut buf = self.reader.buffer();
+        while !buf.is_empty() {
+            let (read, packet) = parser.feed_checked(buf)?;
+            buf = &buf[read..];
+
+            match packet {
+                // Corrupt packets are retried rather than returned
+                Some(Err(ref packet)) if packet.kind == Kind::Packet => (),
+                Some(_) => return Ok(true),
+                None => (),
+            }
+        }
+        Ok(false)
+    }
+}
+impl DynGdbServer {
+    /// Creates a server boxing its reader and writer.
+    ///
+    /// ```rust
+    /// # use gdb_protocol::io::{DynGdbServer, GdbServer};
+    /// # use std::{fs::File, io::{self, BufReader}};
+    /// fn open(path: Option<&str>) -> io::Result<DynGdbServer> {
+    ///     Ok(match path {
+    ///         Some(path) => {
+    ///             let file = File::options().read(true).write(true).open(path)?;
+    ///             GdbServer::boxed(BufReader::new(file.try_clone()?), file)
+    ///         }
+    ///         None => GdbServer::boxed(BufReader::new(io::stdin()), io::stdout()),
+    ///     })
+    /// }
+    /// ```
+    pub fn boxed<R, W>(reader: R, writer: W) -> Self
+    where
+        R: BufRead + Send + 'static,
+        W: Write + Send + 'static,
+    {
+        Self::new(Box::new(reader), Box::new(writer))
     }
 }
 impl<'a> GdbServer<&'a mut &'a [u8], Vec<u8>> {
     pub fn tester(input: &'a mut &'a [u8]) -> Self {
         Self::new(input, Vec::new())
     }
+}
+impl GdbServer<Script, Vec<u8>> {
+    /// Like `tester`, but the input is split into chunks that are
+    /// only made available once the previous chunk has been consumed,
+    /// modelling a peer that waits for the response before sending
+    /// more.
+    pub fn tester_script<I>(chunks: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<Vec<u8>>,
+    {
+        Self::new(Script::new(chunks), Vec::new())
+    }
+}
+impl<R: BufRead> GdbServer<R, Vec<u8>> {
+    /// Takes everything written so far, for servers writing to memory,
+    /// such as `tester`.
     pub fn response(&mut self) -> Vec<u8> {
-        mem::replace(&mut self.writer, Vec::new())
+        mem::take(&mut self.writer)
+    }
+}
+
+/// A reader delivering its input in chunks, each only once the
+/// previous chunk has been fully consumed. See
+/// `GdbServer::tester_script`.
+#[derive(Clone, Debug, Default)]
+pub struct Script {
+    chunks: VecDeque<Vec<u8>>,
+    pos: usize,
+}
+impl Script {
+    pub fn new<I>(chunks: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<Vec<u8>>,
+    {
+        Self {
+            chunks: chunks.into_iter().map(Into::into).collect(),
+            pos: 0,
+        }
+    }
+    /// Return the number of chunks not yet fully consumed.
+    pub fn remaining(&self) -> usize {
+        self.chunks.len()
+    }
+}
+impl Read for Script {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.fill_buf()?.read(buf)?;
+        self.consume(read);
+        Ok(read)
+    }
+}
+impl BufRead for Script {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        while matches!(self.chunks.front(), Some(chunk) if self.pos >= chunk.len()) {
+            self.chunks.pop_front();
+            self.pos = 0;
+        }
+        Ok(match self.chunks.front() {
+            Some(chunk) => &chunk[self.pos..],
+            None => &[],
+        })
+    }
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
     }
 }
+
 impl<R, W> GdbServer<R, W>
 where
     R: BufRead,
     W: Write,
 {
+    /// Creates a server over any `Transport`.
+    ///
+    /// ```rust
+    /// # use gdb_protocol::{io::{GdbServer, Transport}, Error};
+    /// fn echo<T: Transport>(transport: T) -> Result<(), Error> {
+    ///     if let Some(peer) = transport.peer() {
+    ///         println!("Connected to {}", peer);
+    ///     }
+    ///     let mut server = GdbServer::serve(transport)?;
+    ///     while let Some(packet) = server.next_packet()? {
+    ///         server.dispatch(&packet)?;
+    ///     }
+    ///     Ok(())
+    /// }
+    /// echo((&b"$g#67+"[..], Vec::new()))?;
+    /// # Ok::<(), Error>(())
+    /// ```
+    pub fn serve<T>(transport: T) -> Result<Self, Error>
+    where
+        T: Transport<Reader = R, Writer = W>,
+    {
+        let (reader, writer) = transport.split()?;
+        Ok(Self::new(reader, writer))
+    }
     pub fn new(reader: R, writer: W) -> Self {
+        Self::with_parser(reader, writer, Parser::default())
+    }
+}
+impl<R, W, C> GdbServer<R, W, C>
+where
+    R: BufRead,
+    W: Write,
+    C: Checksum,
+{
+    /// Creates a server receiving packets through `parser`, which
+    /// may verify checksums with an algorithm of its own, see
+    /// `Parser::with_checksum`. The packets the server builds itself
+    /// use the same algorithm, while dispatched ones should be built
+    /// with `CheckedPacket::from_data_with`.
+    pub fn with_parser(reader: R, writer: W, parser: Parser<C>) -> Self {
         Self {
             reader,
             writer,
-            parser: Parser::default(),
+            parser,
+            pending_stops: VecDeque::new(),
+            pending_packets: VecDeque::new(),
+            max_send_size: None,
+            verify_checksums: true,
+            verify_notifications: false,
+            coalesce_ack: false,
+            pending_ack: false,
+            unsupported_replies: 0,
+            disconnected: false,
+            trace: None,
+            ack: b'+',
+            nack: b'-',
+            // Same as GDB's own limit for packets it receives
+            max_retransmits: 3,
+            ack_policy: Box::new(DefaultAckPolicy),
+            non_stop: false,
+            dedup: false,
+            last_received: None,
+            duplicates: 0,
+            checksum_mismatches: 0,
+            session_byte_limit: None,
+        }
+    }
+
+    /// Returns false once the reader has reached EOF, meaning the peer
+    /// has disconnected. After that, dispatching fails with
+    /// `Error::Disconnected` instead of writing to a dead connection.
+    /// Only `next_packet` and the like detect this: EOF while waiting
+    /// for the acknowledgment of a dispatched packet doesn't count, so
+    /// packets received before it can still be replied to.
+    pub fn is_connected(&self) -> bool {
+        !self.disconnected
+    }
+    fn check_connected(&self) -> Result<(), Error> {
+        if self.disconnected {
+            Err(Error::Disconnected)
+        } else {
+            Ok(())
+        }
+    }
+    /// Disabling checksum verification makes the server return every
+    /// packet as if it was valid, and acknowledge it with `+`. This
+    /// is meant for analyzing captures of lossy links, and is not safe
+    /// to use over a real transport, where corrupted packets should be
+    /// retransmitted. Packets returned despite a mismatch are counted,
+    /// see `checksum_mismatches`.
+    pub fn set_verify_checksums(&mut self, verify: bool) {
+        self.verify_checksums = verify;
+    }
+    /// Returns how many packets were returned even though their
+    /// checksum didn't match, see `set_verify_checksums` and
+    /// `set_verify_notifications`.
+    pub fn checksum_mismatches(&self) -> usize {
+        self.checksum_mismatches
+    }
+    /// The protocol specifies that the checksum of notifications
+    /// should not be checked, so by default they are returned even if
+    /// it doesn't match. Enabling verification returns notifications
+    /// with a bad checksum as `Error::ChecksumMismatch` instead, for
+    /// peers which take care to checksum them. Notifications can't be
+    /// denied, so they're not retransmitted either way.
+    pub fn set_verify_notifications(&mut self, verify: bool) {
+        self.verify_notifications = verify;
+    }
+    /// Coalescing acknowledgments defers the `+` sent for a received
+    /// packet, so it is written together with the next dispatched
+    /// reply rather than in its own write. This saves a write (and
+    /// likely a syscall) per exchange, at the cost of GDB seeing the
+    /// acknowledgment later, which may cause a retransmit if the
+    /// handler is slow. A deferred acknowledgment is always written
+    /// before reading the next packet, or by `flush`.
+    pub fn set_coalesce_ack(&mut self, coalesce: bool) {
+        self.coalesce_ack = coalesce;
+    }
+    /// Sets the maximum size of an encoded packet, as negotiated with
+    /// `qSupported`'s `PacketSize`. Dispatching a larger packet fails
+    /// with `Error::PacketTooLarge` without sending anything, so the
+    /// reply can be split up instead.
+    pub fn set_max_send_size(&mut self, max: usize) {
+        self.max_send_size = Some(max);
+    }
+    /// Gives access to the parser of received packets, to configure it
+    /// with `Parser::set_max_len`, `Parser::set_strict` or
+    /// `Parser::set_implicit_start`.
+    pub fn parser_mut(&mut self) -> &mut Parser<C> {
+        &mut self.parser
+    }
+    /// Sets the bytes acknowledging a packet (`+` by default) and
+    /// asking for its retransmission (`-` by default), for peers using
+    /// a variant of the protocol. They are used in both directions.
+    pub fn set_ack_bytes(&mut self, positive: u8, negative: u8) {
+        self.ack = positive;
+        self.nack = negative;
+    }
+    /// Sets how many times in a row a packet may be retransmitted,
+    /// both when the peer denies one of ours and when we deny one of
+    /// theirs, after which `Error::RetransmitLimitExceeded` is
+    /// returned rather than retrying forever over a broken link. The
+    /// default is 3, like GDB.
+    pub fn set_max_retransmits(&mut self, max: usize) {
+        self.max_retransmits = max;
+    }
+    /// Limits the bytes read from the peer during the whole session,
+    /// for peers which can't be trusted not to send data forever. Once
+    /// more than `limit` bytes were read, counting garbage, interrupts
+    /// and acknowledgments as well as packets, reading fails with
+    /// `Error::SessionLimitExceeded`, now and on every later read. The
+    /// limit is checked in between reads, so a packet crossing it is
+    /// still returned. There is no limit by default.
+    pub fn set_session_byte_limit(&mut self, limit: usize) {
+        self.session_byte_limit = Some(limit);
+    }
+    /// Detecting duplicates skips a packet identical to the previous
+    /// one, if it was received before anything was dispatched in
+    /// reply. That means the peer retransmitted it, having missed the
+    /// acknowledgment, and handling it again would repeat a command
+    /// such as a memory write. The duplicate is acknowledged, but not
+    /// returned. This is disabled by default.
+    pub fn set_dedup(&mut self, dedup: bool) {
+        self.dedup = dedup;
+        self.last_received = None;
+    }
+    /// Returns how many packets were skipped as duplicates, see
+    /// `set_dedup`.
+    pub fn duplicates(&self) -> usize {
+        self.duplicates
+    }
+    /// Replaces the `DefaultAckPolicy`, deciding how received packets
+    /// are acknowledged. This is meant for testing, and for peers which
+    /// don't quite follow the protocol.
+    pub fn set_ack_policy(&mut self, policy: Box<dyn AckPolicy>) {
+        self.ack_policy = policy;
+    }
+    /// Records whether GDB enabled non-stop mode with `QNonStop`, in
+    /// which stop events are to be reported with `notify` rather than
+    /// as replies. See `commands::Command::SetNonStop`.
+    pub fn set_non_stop(&mut self, enable: bool) {
+        self.non_stop = enable;
+    }
+    /// Returns true if non-stop mode is enabled, see `set_non_stop`.
+    pub fn is_non_stop(&self) -> bool {
+        self.non_stop
+    }
+    /// Installs a hook observing the raw bytes exchanged with the peer:
+    /// everything read, before it's parsed, and everything written,
+    /// after it's encoded, including acknowledgments. This is meant
+    /// for debugging, such as dumping a hex trace of a session.
+    pub fn set_trace<F>(&mut self, hook: F)
+    where
+        F: FnMut(Direction, &[u8]) + Send + 'static,
+    {
+        self.trace = Some(Box::new(hook));
+    }
+    fn trace(&mut self, direction: Direction, bytes: &[u8]) {
+        if let Some(trace) = &mut self.trace {
+            trace(direction, bytes);
         }
     }
+    fn write_traced(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.trace(Direction::Out, bytes);
+        self.writer.write_all(bytes)?;
+        Ok(())
+    }
 
     pub fn next_packet(&mut self) -> Result<Option<CheckedPacket>, Error> {
+        self.next(false).map(|event| match event {
+            Some(Event::Packet(packet)) => Some(packet),
+            Some(Event::Interrupt) => unreachable!("interrupts are not reported"),
+            None => None,
+        })
+    }
+    /// Like `next_packet`, but skips packets for which `pred` returns
+    /// false. Skipped packets are acknowledged as usual, and replied
+    /// to as unsupported (except for notifications, which get no
+    /// reply), so the peer isn't left waiting for them.
+    pub fn next_packet_matching<F>(&mut self, pred: F) -> Result<Option<CheckedPacket>, Error>
+    where
+        F: Fn(&CheckedPacket) -> bool,
+    {
         loop {
-            let buf = self.reader.fill_buf()?;
-            if buf.is_empty() {
-                break Ok(None);
+            match self.next_packet()? {
+                Some(packet) if !pred(&packet) => {
+                    if packet.kind == Kind::Packet {
+                        self.reply_unsupported()?;
+                    }
+                }
+                result => break Ok(result),
             }
-
-            // println!("{:?}", std::str::from_utf8(buf));
-            let (read, packet) = self.parser.feed(buf)?;
-            self.reader.consume(read);
-
-            if let Some(packet) = packet {
-                break Ok(match packet.kind {
-                    Kind::Packet => match packet.check() {
-                        Some(checked) => {
-                            self.writer.write_all(&[b'+'])?;
-                            Some(checked)
-                        }
-                        None => {
-                            self.writer.write_all(&[b'-'])?;
-                            continue; // Retry
-                        }
-                    },
-                    // Protocol specifies notifications should not be checked
-                    Kind::Notification => packet.check(),
-                });
+        }
+    }
+    /// Calls `handler` with every received packet until the peer
+    /// disconnects, dispatching the reply it returns, or replying
+    /// unsupported if it returns `None`. Notifications get no reply.
+    ///
+    /// ```rust
+    /// # use gdb_protocol::{commands, io::GdbServer, Error};
+    /// # let mut input: &[u8] = b"$?#3f+$qTStatus#49+";
+    /// # let mut server = GdbServer::tester(&mut input);
+    /// server.run(|packet| match &packet.data[..] {
+    ///     b"?" => Some(commands::StopReply::signal(5)),
+    ///     _ => None,
+    /// })?;
+    /// # assert_eq!(server.response(), b"+$S05#b8+$#00");
+    /// # Ok::<(), Error>(())
+    /// ```
+    pub fn run<F>(&mut self, mut handler: F) -> Result<(), Error>
+    where
+        F: FnMut(&CheckedPacket) -> Option<CheckedPacket>,
+    {
+        while let Some(packet) = self.next_packet()? {
+            let reply = handler(&packet);
+            if packet.kind == Kind::Packet {
+                match reply {
+                    Some(reply) => self.dispatch(&reply)?,
+                    None => self.reply_unsupported()?,
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Like `next_packet`, but also reports interrupts sent by GDB,
+    /// which `next_packet` skips as garbage. Packets are acknowledged
+    /// as usual, while interrupts are not.
+    pub fn next_event(&mut self) -> Result<Option<Event>, Error> {
+        self.next(true)
+    }
+    fn next(&mut self, interrupts: bool) -> Result<Option<Event>, Error> {
+        self.flush()?;
+        if let Some(packet) = self.pending_packets.pop_front() {
+            return Ok(Some(Event::Packet(packet)));
+        }
+        loop {
+            match self.receive(interrupts) {
+                Ok(Some(Event::Packet(packet))) if self.is_duplicate(&packet) => (),
+                // Only reading marks the server disconnected, see
+                // `is_connected`
+                result @ (Ok(None) | Err(Error::UnexpectedEof)) => {
+                    self.disconnected = true;
+                    break result;
+                }
+                result => break result,
+            }
+        }
+    }
+    /// Checks for a retransmitted packet, see `set_dedup`.
+    fn is_duplicate(&mut self, packet: &CheckedPacket) -> bool {
+        if !self.dedup || packet.kind != Kind::Packet {
+            return false;
+        }
+        if self.last_received.as_ref() == Some(packet) {
+            self.duplicates += 1;
+            return true;
+        }
+        self.last_received = Some(packet.clone());
+        false
+    }
+    /// Reads and acknowledges the next event.
+    fn receive(&mut self, interrupts: bool) -> Result<Option<Event>, Error> {
+        let mut retransmits = 0;
+        loop {
+            let trace = &mut self.trace;
+            let mut trace = |direction: Direction, bytes: &[u8]| {
+                if let Some(trace) = trace {
+                    trace(direction, bytes);
+                }
+            };
+            let (reader, parser) = (&mut self.reader, &mut self.parser);
+            let limit = self.session_byte_limit;
+            let event = match read_event(reader, parser, interrupts, limit, &mut trace) {
+                Err(Error::ChecksumMismatch(packet))
+                    if !self.verify_checksums
+                        || (packet.kind == Kind::Notification && !self.verify_notifications) =>
+                {
+                    self.checksum_mismatches += 1;
+                    Ok(Some(Event::Packet(CheckedPacket::assume_checked(packet))))
+                }
+                result => result,
+            };
+            let action = match &event {
+                Ok(Some(Event::Packet(packet))) => {
+                    self.ack_policy.on_packet(CheckResult::Valid(packet))
+                }
+                Err(Error::ChecksumMismatch(packet)) => {
+                    self.ack_policy.on_packet(CheckResult::Invalid(packet))
+                }
+                _ => AckAction::None,
+            };
+            match action {
+                AckAction::SendPositive if self.coalesce_ack && event.is_ok() => {
+                    self.pending_ack = true;
+                }
+                AckAction::SendPositive => self.write_traced(&[self.ack])?,
+                AckAction::SendNegative => {
+                    if retransmits == self.max_retransmits {
+                        return Err(Error::RetransmitLimitExceeded);
+                    }
+                    retransmits += 1;
+                    self.write_traced(&[self.nack])?;
+                    continue; // Retry
+                }
+                AckAction::None => (),
             }
+            break event;
         }
     }
     /// Sends a packet, retrying upon any failed checksum verification
-    /// on the remote.
+    /// on the remote. A packet the remote sends instead of
+    /// acknowledging this one is acknowledged and queued, to be
+    /// returned by `next_packet`, and ends the wait for the ack.
     pub fn dispatch(&mut self, packet: &CheckedPacket) -> Result<(), Error> {
+        self.check_connected()?;
+        if let Some(max) = self.max_send_size {
+            let len = packet.encoded_len();
+            if len > max {
+                return Err(Error::PacketTooLarge {
+                    kind: packet.kind,
+                    len,
+                    max,
+                });
+            }
+        }
+        let mut encoded = Vec::with_capacity(1 + packet.encoded_len());
+        if mem::take(&mut self.pending_ack) {
+            encoded.push(self.ack);
+        }
+        let ack_len = encoded.len();
+        packet.encode(&mut encoded)?;
+
+        let mut output = &encoded[..];
+        let mut retransmits = 0;
         loop {
-            packet.encode(&mut self.writer)?;
+            self.write_traced(output)?;
             self.writer.flush()?;
+            output = &encoded[ack_len..];
 
             // TCP guarantees the order of packets, so theoretically
             // '+' or '-' will always be sent directly after a packet
             // is received.
-            let buf = self.reader.fill_buf()?;
-            match buf.first() {
-                Some(b'+') => {
+            let ack = self.reader.fill_buf()?.first().copied();
+            if let Some(ack) = ack.filter(|&b| b == self.ack || b == self.nack) {
+                self.trace(Direction::In, &[ack]);
+            }
+            match ack {
+                Some(b) if b == self.ack => {
                     self.reader.consume(1);
+                    self.parser.skip_ack();
                     break;
                 },
-                Some(b'-') => {
+                Some(b) if b == self.nack => {
                     self.reader.consume(1);
-                    if packet.is_valid() {
+                    self.parser.skip_ack();
+                    let actual = packet.actual_checksum_with::<C>();
+                    if packet.expected_checksum().ok() == Some(actual) {
                         // Well, ok, not our fault. The packet is
                         // definitely valid, let's re-try
+                        if retransmits == self.max_retransmits {
+                            return Err(Error::RetransmitLimitExceeded);
+                        }
+                        retransmits += 1;
                         continue;
                     } else {
                         // Oh... so the user actually tried to send a
@@ -117,75 +824,1295 @@ where
                         return Err(Error::InvalidChecksum);
                     }
                 },
+                Some(b'$') | Some(b'%') => {
+                    // The peer sent a packet before acknowledging ours,
+                    // so queue it for next_packet. It may never send an
+                    // ack at all, such as in no-ack mode, so stop
+                    // waiting for one.
+                    if let Some(Event::Packet(packet)) = self.receive(false)? {
+                        if !self.is_duplicate(&packet) {
+                            self.pending_packets.push_back(packet);
+                        }
+                    }
+                    self.flush()?;
+                    break;
+                }
+                // The packet was written, and packets queued above are
+                // still to be handled, so only reading marks the server
+                // disconnected
+                None => break,
                 // Never mind... Just... hope for the best?
                 _ => break,
             }
         }
+        // The peer has seen this reply, so any packet it sends next is
+        // a new one
+        self.last_received = None;
         Ok(())
     }
-}
+    /// Sends a packet with a wrong checksum, to test how the peer
+    /// handles corrupted packets. If the peer asks for a retransmit as
+    /// it should, the packet is retransmitted like `dispatch` does,
+    /// with the right checksum. Returns whether the peer did ask.
+    ///
+    /// This is a fault injection tool, not meant for normal operation.
+    pub fn dispatch_with_bad_checksum(&mut self, packet: &CheckedPacket) -> Result<bool, Error> {
+        let mut corrupt = packet.clone().invalidate_check();
+        let wrong = !packet.actual_checksum_with::<C>();
+        write!(&mut corrupt.checksum[..], "{:02x}", wrong)?;
+        let corrupt = CheckedPacket::assume_checked(corrupt);
+        match self.dispatch(&corrupt) {
+            Err(Error::InvalidChecksum) => {
+                self.dispatch(packet)?;
+                Ok(true)
+            }
+            result => result.map(|()| false),
+        }
+    }
+    /// Tells GDB that the last received packet is not supported, by
+    /// dispatching an empty packet. See `commands::Unsupported`.
+    pub fn reply_unsupported(&mut self) -> Result<(), Error> {
+        // The empty packet of `commands::Unsupported`, summed with `C`
+        let packet = CheckedPacket::from_data_with::<C>(Kind::Packet, Vec::new());
+        self.dispatch(&packet)?;
+        self.unsupported_replies += 1;
+        Ok(())
+    }
+    /// Returns how many times `reply_unsupported` has been used.
+    pub fn unsupported_replies(&self) -> usize {
+        self.unsupported_replies
+    }
+    /// Recovers from a desynchronized stream, by discarding any
+    /// partially received packet and skipping all input up to the next
+    /// start of a packet. Returns the number of skipped bytes.
+    pub fn resync(&mut self) -> Result<usize, Error> {
+        self.parser.reset();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::packet::UncheckedPacket;
+        let mut discarded = 0;
+        loop {
+            let buf = self.reader.fill_buf()?;
+            if buf.is_empty() {
+                break Ok(discarded);
+            }
+            let skip = memchr::memchr2(b'$', b'%', buf);
+            let len = skip.unwrap_or(buf.len());
+            if let Some(trace) = &mut self.trace {
+                trace(Direction::In, &buf[..len]);
+            }
+            match skip {
+                Some(start) => {
+                    self.reader.consume(start);
+                    self.parser.skip(start);
+                    break Ok(discarded + start);
+                }
+                None => {
+                    self.reader.consume(len);
+                    self.parser.skip(len);
+                    discarded += len;
+                }
+            }
+        }
+    }
+    /// Writes any deferred acknowledgment, and flushes the writer.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        if mem::take(&mut self.pending_ack) {
+            self.write_traced(&[self.ack])?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
 
-    #[test]
-    fn it_acknowledges_valid_packets() {
-        let mut input: &[u8] = b"$packet#78";
-        let mut tester = GdbServer::tester(&mut input);
-        assert_eq!(
-            tester.next_packet().unwrap(),
-            Some(CheckedPacket::from_data(Kind::Packet, b"packet".to_vec()))
-        );
-        assert_eq!(tester.response(), b"+");
+    /// Queues an asynchronous stop event, such as `T05thread:1;`,
+    /// to be reported to GDB.
+    ///
+    /// Notifications are not acknowledged with `+`/`-` like regular
+    /// packets. Instead, GDB acknowledges a `%Stop:` notification by
+    /// sending `vStopped`, to which the stub replies with the next
+    /// queued stop event as a regular packet, or `OK` once the queue
+    /// is empty. Only one notification may be in flight at a time, so
+    /// this only sends `%Stop:<data>` if no other stop event is
+    /// pending; otherwise the event is delivered later as a reply to
+    /// `vStopped`, see `notification_acked`.
+    pub fn notify(&mut self, data: Vec<u8>) -> Result<(), Error> {
+        self.check_connected()?;
+        if self.pending_stops.is_empty() {
+            self.flush()?;
+            let mut notification = b"Stop:".to_vec();
+            notification.extend_from_slice(&data);
+            let mut encoded = Vec::new();
+            CheckedPacket::from_data_with::<C>(Kind::Notification, notification)
+                .encode(&mut encoded)?;
+            self.write_traced(&encoded)?;
+            self.writer.flush()?;
+        }
+        self.pending_stops.push_back(data);
+        Ok(())
     }
-    #[test]
-    fn it_acknowledges_invalid_packets() {
-        let mut input: &[u8] = b"$packet#99";
-        let mut tester = GdbServer::tester(&mut input);
-        assert_eq!(tester.next_packet().unwrap(), None);
-        assert_eq!(tester.response(), b"-");
+    /// Handles `packet` if it is a `vStopped` acknowledgment of a stop
+    /// notification, returning whether it was. The acknowledged event
+    /// is removed from the queue and the next pending event (or `OK`,
+    /// if there is none) is dispatched as the reply. Any other packet
+    /// is left untouched for the caller to handle.
+    pub fn notification_acked(&mut self, packet: &CheckedPacket) -> Result<bool, Error> {
+        if packet.kind != Kind::Packet || packet.data != b"vStopped" {
+            return Ok(false);
+        }
+        self.pending_stops.pop_front();
+        let reply = match self.pending_stops.front() {
+            Some(data) => CheckedPacket::from_data_with::<C>(Kind::Packet, data.clone()),
+            None => CheckedPacket::from_data_with::<C>(Kind::Packet, b"OK".to_vec()),
+        };
+        self.dispatch(&reply)?;
+        Ok(true)
     }
-    #[test]
-    fn it_ignores_garbage() {
-        let mut input: &[u8] =
-            b"<garbage here yada yaya> $packet#13 $packet#37 more garbage $GARBA#GE-- $packet#78";
-        let mut tester = GdbServer::tester(&mut input);
-        assert_eq!(
-            tester.next_packet().unwrap(),
-            Some(CheckedPacket::from_data(Kind::Packet, b"packet".to_vec()))
-        );
-        assert_eq!(tester.response(), b"---+");
+}
+
+/// The receiving side of a server without any I/O: bytes read from
+/// the transport are pushed in, complete packets are taken out, and
+/// the acknowledgments a server would send are collected for the
+/// caller to write back. This works with any event loop or runtime.
+///
+/// Packets are acknowledged like `GdbServer::next_packet` does, but
+/// there is no limit on retransmits.
+#[derive(Clone)]
+pub struct PacketChannel<C = Gdb8BitSum> {
+    parser: Parser<C>,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    capacity: usize,
+}
+impl Default for PacketChannel {
+    fn default() -> Self {
+        Self::new(Parser::default())
     }
-    #[test]
-    fn it_dispatches() {
-        let mut input: &[u8] = b"";
-        let mut tester = GdbServer::tester(&mut input);
-        tester.dispatch(&CheckedPacket::from_data(Kind::Packet, b"hOi!!".to_vec())).unwrap();
-        assert_eq!(tester.response(), b"$hOi!!#62");
+}
+impl<C: Checksum> PacketChannel<C> {
+    /// Creates a channel buffering up to `BUF_SIZE` bytes.
+    pub fn new(parser: Parser<C>) -> Self {
+        Self::with_capacity(parser, BUF_SIZE)
     }
-    #[test]
-    fn it_resends() {
-        let mut input: &[u8] = b"-+";
-        let mut tester = GdbServer::tester(&mut input);
-        tester.dispatch(&CheckedPacket::from_data(Kind::Packet, b"IMBATMAN".to_vec())).unwrap();
-        assert_eq!(tester.response(), b"$IMBATMAN#49$IMBATMAN#49");
+    /// Creates a channel buffering up to `capacity` bytes which
+    /// `try_next` hasn't parsed yet. This doesn't limit the size of a
+    /// packet, see `Parser::set_max_len` for that.
+    pub fn with_capacity(parser: Parser<C>, capacity: usize) -> Self {
+        Self {
+            parser,
+            input: Vec::new(),
+            output: Vec::new(),
+            capacity,
+        }
     }
-    #[test]
-    fn it_complains_when_the_user_lies() {
-        let mut input: &[u8] = b"-";
-        let mut tester = GdbServer::tester(&mut input);
-        let result = tester.dispatch(&CheckedPacket::assume_checked(UncheckedPacket {
-            kind: Kind::Packet,
-            data: b"This sentence is false. (dontthinkaboutitdontthinkaboutit)".to_vec(),
-            checksum: *b"FF",
-        }));
-        if let Err(Error::InvalidChecksum) = result {
-        } else {
-            panic!("Expected error InvalidChecksum, got {:?}", result);
+    /// Buffers as many of the bytes read from the transport as there
+    /// is room for, and returns how many that was. The rest should be
+    /// pushed again later: calling `try_next` until it returns `None`
+    /// always empties the buffer.
+    pub fn push_bytes(&mut self, bytes: &[u8]) -> usize {
+        let room = self.capacity.saturating_sub(self.input.len());
+        let len = cmp::min(room, bytes.len());
+        self.input.extend_from_slice(&bytes[..len]);
+        len
+    }
+    /// Returns the next complete packet, or `None` if more bytes are
+    /// needed. Corrupted packets are denied and skipped, to be
+    /// retransmitted by the peer.
+    pub fn try_next(&mut self) -> Option<Result<CheckedPacket, Error>> {
+        loop {
+            let before = self.parser.bytes_consumed();
+            let result = self.parser.feed_checked(&self.input);
+            let read = match &result {
+                Ok((read, _)) => *read,
+                // Skip at least a byte, so the error isn't returned forever
+                Err(_) => cmp::min(
+                    cmp::max(self.parser.bytes_consumed() - before, 1),
+                    self.input.len(),
+                ),
+            };
+            self.input.drain(..read);
+
+            let packet = match result {
+                Ok((_, Some(Ok(packet)))) => packet,
+                // Protocol specifies notifications should not be checked
+                Ok((_, Some(Err(packet)))) if packet.kind == Kind::Notification => {
+                    CheckedPacket::assume_checked(packet)
+                }
+                Ok((_, Some(Err(_)))) => {
+                    self.output.push(b'-');
+                    continue;
+                }
+                Ok((_, None)) => break None,
+                Err(err) => break Some(Err(err)),
+            };
+            if packet.kind == Kind::Packet {
+                self.output.push(b'+');
+            }
+            break Some(Ok(packet));
+        }
+    }
+    /// Takes the acknowledgments to write to the transport.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        mem::take(&mut self.output)
+    }
+}
+
+/// Packets tunneled over a transport which delivers whole messages
+/// reliably, such as a WebSocket, one packet per message. Such a
+/// transport makes acknowledgments redundant, so none are sent.
+pub struct FramedTunnel;
+impl FramedTunnel {
+    /// Encodes a packet as a message.
+    pub fn to_message(packet: &CheckedPacket) -> Vec<u8> {
+        let mut message = Vec::with_capacity(packet.encoded_len());
+        packet
+            .encode(&mut message)
+            .expect("writing to a Vec never fails");
+        message
+    }
+    /// Decodes the packet of a message. The message must consist of
+    /// exactly one packet: a truncated one fails with
+    /// `Error::UnexpectedEof`, and anything following it with
+    /// `Error::Malformed`. Messages aren't retransmitted, so a corrupted
+    /// packet fails with `Error::ChecksumMismatch`.
+    pub fn from_message(message: &[u8]) -> Result<CheckedPacket, Error> {
+        let mut parser = Parser::default();
+        parser.set_strict(true);
+        match parser.feed_checked(message)? {
+            (read, Some(Ok(packet))) if read == message.len() => Ok(packet),
+            (read, Some(Ok(_))) => Err(Error::Malformed(message[read..].to_vec())),
+            (_, Some(Err(packet))) => Err(Error::ChecksumMismatch(packet)),
+            (_, None) => Err(Error::UnexpectedEof),
+        }
+    }
+}
+
+/// The host side of a connection, which is what GDB itself does. The
+/// acknowledgment and retransmit rules are the same in both
+/// directions, so this shares the implementation of `GdbServer`.
+pub struct GdbClient<R, W, C = Gdb8BitSum>
+where
+    R: BufRead,
+    W: Write,
+{
+    inner: GdbServer<R, W, C>,
+    outstanding: VecDeque<CheckedPacket>,
+    received: VecDeque<CheckedPacket>,
+}
+
+/// Returns false for the packets the stub must not reply to, `R` and
+/// `k`.
+fn expects_reply(packet: &CheckedPacket) -> bool {
+    packet.kind == Kind::Packet && !matches!(&packet.data[..], [b'R', ..] | b"k")
+}
+/// Returns false for notifications, and for the `O` packets the stub
+/// may send before the reply to a request such as `qRcmd`.
+fn is_reply(packet: &CheckedPacket) -> bool {
+    packet.kind == Kind::Packet && (packet.data == b"OK" || packet.data.first() != Some(&b'O'))
+}
+
+impl GdbClient<BufReader<TcpStream>, TcpStream> {
+    /// Connects to a stub, setting up the stream the same way
+    /// `GdbServer::from_stream` does.
+    pub fn connect<A>(addr: A) -> Result<Self, Error>
+    where
+        A: ToSocketAddrs,
+    {
+        let stream = TcpStream::connect(addr)?;
+        Ok(Self::from_server(GdbServer::from_stream(stream)?))
+    }
+}
+impl<R, W> GdbClient<R, W>
+where
+    R: BufRead,
+    W: Write,
+{
+    pub fn new(reader: R, writer: W) -> Self {
+        Self::from_server(GdbServer::new(reader, writer))
+    }
+}
+impl<R, W, C> GdbClient<R, W, C>
+where
+    R: BufRead,
+    W: Write,
+    C: Checksum,
+{
+    /// Creates a client receiving packets through `parser`, see
+    /// `GdbServer::with_parser`.
+    pub fn with_parser(reader: R, writer: W, parser: Parser<C>) -> Self {
+        Self::from_server(GdbServer::with_parser(reader, writer, parser))
+    }
+    fn from_server(inner: GdbServer<R, W, C>) -> Self {
+        Self {
+            inner,
+            outstanding: VecDeque::new(),
+            received: VecDeque::new(),
+        }
+    }
+
+    /// Receives the next packet from the stub, see
+    /// `GdbServer::next_packet`. A packet (as opposed to a
+    /// notification or console output) is taken to be the reply to the
+    /// oldest outstanding request. Packets kept by `send_and_recv` are
+    /// returned first.
+    pub fn next_packet(&mut self) -> Result<Option<CheckedPacket>, Error> {
+        if let Some(packet) = self.received.pop_front() {
+            return Ok(Some(packet));
+        }
+        let packet = self.inner.next_packet()?;
+        if matches!(&packet, Some(packet) if is_reply(packet)) {
+            self.outstanding.pop_front();
+        }
+        Ok(packet)
+    }
+    /// Gives access to the parser of received packets, see
+    /// `GdbServer::parser_mut`.
+    pub fn parser_mut(&mut self) -> &mut Parser<C> {
+        self.inner.parser_mut()
+    }
+    /// Sends a packet to the stub, see `GdbServer::dispatch`. The
+    /// packet is outstanding until its reply is received, unless it is
+    /// one that gets no reply, such as `R`.
+    pub fn send(&mut self, packet: &CheckedPacket) -> Result<(), Error> {
+        self.inner.dispatch(packet)?;
+        if expects_reply(packet) {
+            self.outstanding.push_back(packet.clone());
+        }
+        Ok(())
+    }
+    /// Returns the oldest request which hasn't been replied to yet.
+    /// The protocol is strictly request/response, so replies arrive
+    /// in the order the requests were sent.
+    pub fn outstanding(&self) -> Option<&CheckedPacket> {
+        self.outstanding.front()
+    }
+    /// Sends a request and waits for its reply, after any replies to
+    /// earlier outstanding requests. Those replies, as well as
+    /// notifications and console output received in the meantime, such
+    /// as the output of `qRcmd`, are kept for `next_packet`. Fails
+    /// with `Error::Disconnected` if the stub disconnects before
+    /// replying, and with `Error::Malformed` without sending anything
+    /// if the request is one that gets no reply, such as `R`.
+    pub fn send_and_recv(&mut self, request: &CheckedPacket) -> Result<CheckedPacket, Error> {
+        if !expects_reply(request) {
+            return Err(Error::Malformed(request.data.clone()));
+        }
+        let mut earlier = self.outstanding.len();
+        self.send(request)?;
+        loop {
+            match self.inner.next_packet()? {
+                Some(packet) if !is_reply(&packet) => self.received.push_back(packet),
+                Some(packet) => {
+                    self.outstanding.pop_front();
+                    if earlier == 0 {
+                        break Ok(packet);
+                    }
+                    earlier -= 1;
+                    self.received.push_back(packet);
+                }
+                None => break Err(Error::Disconnected),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{commands::ok_reply, packet::UncheckedPacket};
+
+    #[test]
+    fn it_acknowledges_valid_packets() {
+        let mut input: &[u8] = b"$packet#78";
+        let mut tester = GdbServer::tester(&mut input);
+        assert_eq!(
+            tester.next_packet().unwrap(),
+            Some(CheckedPacket::from_data(Kind::Packet, b"packet".to_vec()))
+        );
+        assert_eq!(tester.response(), b"+");
+    }
+    #[test]
+    fn it_acknowledges_invalid_packets() {
+        let mut input: &[u8] = b"$packet#99";
+        let mut tester = GdbServer::tester(&mut input);
+        assert_eq!(tester.next_packet().unwrap(), None);
+        assert_eq!(tester.response(), b"-");
+    }
+    #[test]
+    fn it_can_skip_checksum_verification() {
+        let mut input: &[u8] = b"$packet#99%notification#99";
+        let mut tester = GdbServer::tester(&mut input);
+        tester.set_verify_checksums(false);
+        assert_eq!(
+            tester.next_packet().unwrap(),
+            Some(CheckedPacket::assume_checked(UncheckedPacket {
+                kind: Kind::Packet,
+                data: b"packet".to_vec(),
+                checksum: *b"99",
+            }))
+        );
+        assert_eq!(tester.next_packet().unwrap().unwrap().data, b"notification");
+        assert_eq!(tester.response(), b"+");
+        assert_eq!(tester.checksum_mismatches(), 2);
+
+        let mut input: &[u8] = b"$OK#9a";
+        let mut tester = GdbServer::tester(&mut input);
+        tester.set_verify_checksums(false);
+        tester.next_packet().unwrap();
+        assert_eq!(tester.checksum_mismatches(), 0);
+    }
+    #[test]
+    fn it_ignores_garbage() {
+        let mut input: &[u8] =
+            b"<garbage here yada yaya> $packet#13 $packet#37 more garbage $GARBA#GE-- $packet#78";
+        let mut tester = GdbServer::tester(&mut input);
+        assert_eq!(
+            tester.next_packet().unwrap(),
+            Some(CheckedPacket::from_data(Kind::Packet, b"packet".to_vec()))
+        );
+        assert_eq!(tester.response(), b"---+");
+    }
+    #[test]
+    fn it_reads_without_acknowledging() {
+        let mut input: &[u8] = b"$packet#99$packet#78";
+        let mut parser = Parser::default();
+        match read_packet(&mut input, &mut parser) {
+            Err(Error::ChecksumMismatch(packet)) => assert_eq!(packet.data, b"packet"),
+            result => panic!("Expected error ChecksumMismatch, got {:?}", result),
+        }
+        assert_eq!(
+            read_packet(&mut input, &mut parser).unwrap(),
+            Some(CheckedPacket::from_data(Kind::Packet, b"packet".to_vec()))
+        );
+        assert_eq!(read_packet(&mut input, &mut parser).unwrap(), None);
+    }
+    #[test]
+    fn it_wraps_tcp_streams() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _addr) = listener.accept().unwrap();
+
+        let mut server = GdbServer::from_stream(stream).unwrap();
+        assert!(server.writer.nodelay().unwrap());
+
+        client.write_all(b"$packet#78").unwrap();
+        assert_eq!(
+            server.next_packet().unwrap(),
+            Some(CheckedPacket::from_data(Kind::Packet, b"packet".to_vec()))
+        );
+        let mut ack = [0];
+        client.read_exact(&mut ack).unwrap();
+        assert_eq!(&ack, b"+");
+    }
+    #[test]
+    fn it_skips_unexpected_bytes_in_strict_mode() {
+        let mut input: &[u8] = b"+x$OK#9a";
+        let mut parser = Parser::default();
+        parser.set_strict(true);
+        match read_packet(&mut input, &mut parser) {
+            Err(Error::UnexpectedByte(b'x')) => (),
+            result => panic!("Expected error UnexpectedByte, got {:?}", result),
+        }
+        assert_eq!(
+            read_packet(&mut input, &mut parser).unwrap(),
+            Some(CheckedPacket::from_data(Kind::Packet, b"OK".to_vec()))
+        );
+    }
+    #[test]
+    fn it_skips_packets_too_large_to_read() {
+        let mut input: &[u8] = b"$0123456789#2d$OK#9a";
+        let mut parser = Parser::default();
+        parser.set_max_len(4);
+        match read_packet(&mut input, &mut parser) {
+            Err(Error::PacketTooLarge { len, .. }) => assert_eq!(len, 10),
+            result => panic!("Expected error PacketTooLarge, got {:?}", result),
+        }
+        assert_eq!(
+            read_packet(&mut input, &mut parser).unwrap(),
+            Some(CheckedPacket::from_data(Kind::Packet, b"OK".to_vec()))
+        );
+    }
+    #[test]
+    fn it_configures_the_parser_of_servers() {
+        let mut input: &[u8] = b"$0123456789#2d$OK#9a";
+        let mut tester = GdbServer::tester(&mut input);
+        tester.parser_mut().set_max_len(4);
+        match tester.next_packet() {
+            Err(Error::PacketTooLarge { len, .. }) => assert_eq!(len, 10),
+            result => panic!("Expected error PacketTooLarge, got {:?}", result),
+        }
+        assert_eq!(tester.next_packet().unwrap(), Some(ok_reply()));
+    }
+    #[test]
+    fn it_detects_buffered_packets() {
+        let input: &[u8] = b"$packet#78$pack";
+        let mut server = GdbServer::new(BufReader::new(input), Vec::new());
+        server.reader.fill_buf().unwrap();
+        assert!(server.has_buffered_packet().unwrap());
+        assert_eq!(
+            server.next_packet().unwrap(),
+            Some(CheckedPacket::from_data(Kind::Packet, b"packet".to_vec()))
+        );
+        assert!(!server.has_buffered_packet().unwrap());
+
+        let input: &[u8] = b"$packet#78$packet#99$packet#78";
+        let mut server = GdbServer::new(BufReader::new(input), Vec::new());
+        server.next_packet().unwrap();
+        assert!(server.has_buffered_packet().unwrap());
+        server.next_packet().unwrap();
+        assert!(!server.has_buffered_packet().unwrap());
+        assert_eq!(server.writer, b"+-+");
+    }
+    #[test]
+    fn it_reports_interrupts() {
+        let mut input: &[u8] = b"\x03$g#67";
+        let mut tester = GdbServer::tester(&mut input);
+        assert_eq!(tester.next_event().unwrap(), Some(Event::Interrupt));
+        assert_eq!(tester.response(), b"");
+        assert_eq!(
+            tester.next_event().unwrap(),
+            Some(Event::Packet(CheckedPacket::from_data(
+                Kind::Packet,
+                b"g".to_vec()
+            )))
+        );
+        assert_eq!(tester.response(), b"+");
+        assert_eq!(tester.next_event().unwrap(), None);
+    }
+    #[test]
+    fn it_reads_large_packets() {
+        let packet = CheckedPacket::from_data(Kind::Packet, vec![b'a'; 3 * BUF_SIZE]);
+        let mut input = Vec::new();
+        packet.encode(&mut input).unwrap();
+
+        for &capacity in &[16, BUF_SIZE, 4 * BUF_SIZE] {
+            let mut server = GdbServer::with_capacity(capacity, &input[..], Vec::new());
+            assert_eq!(server.reader.capacity(), capacity);
+            assert_eq!(server.next_packet().unwrap(), Some(packet.clone()));
+            assert_eq!(server.writer, b"+");
+        }
+    }
+    #[test]
+    fn it_resyncs() {
+        let mut input: &[u8] = b"garbage$packet#78";
+        let mut tester = GdbServer::tester(&mut input);
+        tester.parser.feed(b"$pack").unwrap();
+        assert_eq!(tester.resync().unwrap(), 7);
+        assert_eq!(tester.parser.bytes_consumed(), 12);
+        assert_eq!(
+            tester.next_packet().unwrap(),
+            Some(CheckedPacket::from_data(Kind::Packet, b"packet".to_vec()))
+        );
+        assert_eq!(tester.resync().unwrap(), 0);
+        assert_eq!(tester.parser.bytes_consumed(), 22);
+    }
+    #[test]
+    fn it_reports_truncated_packets() {
+        let mut input: &[u8] = b"$packet#78$packet#7";
+        let mut tester = GdbServer::tester(&mut input);
+        assert!(tester.next_packet().unwrap().is_some());
+        match tester.next_packet() {
+            Err(Error::UnexpectedEof) => (),
+            result => panic!("Expected error UnexpectedEof, got {:?}", result),
+        }
+        assert_eq!(tester.response(), b"+");
+    }
+    #[test]
+    fn it_detects_disconnects() {
+        let mut input: &[u8] = b"$packet#78";
+        let mut tester = GdbServer::tester(&mut input);
+        assert!(tester.next_packet().unwrap().is_some());
+        assert!(tester.is_connected());
+        assert_eq!(tester.next_packet().unwrap(), None);
+        assert!(!tester.is_connected());
+
+        match tester.dispatch(&crate::commands::ok_reply()) {
+            Err(Error::Disconnected) => (),
+            result => panic!("Expected error Disconnected, got {:?}", result),
+        }
+        assert_eq!(tester.response(), b"+");
+    }
+    #[test]
+    fn it_replies_to_packets_received_before_disconnects() {
+        let mut input: &[u8] = b"$qC#b4";
+        let mut tester = GdbServer::tester(&mut input);
+        tester.dispatch(&ok_reply()).unwrap();
+        assert!(tester.is_connected());
+        assert_eq!(tester.next_packet().unwrap().unwrap().data, b"qC");
+        tester.dispatch(&ok_reply()).unwrap();
+        assert_eq!(tester.response(), b"$OK#9a+$OK#9a");
+
+        assert_eq!(tester.next_packet().unwrap(), None);
+        assert!(!tester.is_connected());
+        match tester.dispatch(&ok_reply()) {
+            Err(Error::Disconnected) => (),
+            result => panic!("Expected error Disconnected, got {:?}", result),
+        }
+
+        // Not even if the input ends in a packet sent instead of the ack
+        let mut input: &[u8] = b"$qC#b";
+        let mut tester = GdbServer::tester(&mut input);
+        match tester.dispatch(&ok_reply()) {
+            Err(Error::UnexpectedEof) => (),
+            result => panic!("Expected error UnexpectedEof, got {:?}", result),
+        }
+        assert!(tester.is_connected());
+        match tester.next_packet() {
+            Err(Error::UnexpectedEof) => (),
+            result => panic!("Expected error UnexpectedEof, got {:?}", result),
+        }
+        assert!(!tester.is_connected());
+    }
+    #[test]
+    fn it_dispatches() {
+        let mut input: &[u8] = b"";
+        let mut tester = GdbServer::tester(&mut input);
+        tester.dispatch(&CheckedPacket::from_data(Kind::Packet, b"hOi!!".to_vec())).unwrap();
+        assert_eq!(tester.response(), b"$hOi!!#62");
+    }
+    #[test]
+    fn it_replies_unsupported() {
+        let mut input: &[u8] = b"";
+        let mut tester = GdbServer::tester(&mut input);
+        tester.reply_unsupported().unwrap();
+        assert_eq!(tester.response(), b"$#00");
+        assert_eq!(tester.unsupported_replies(), 1);
+
+        let mut input: &[u8] = b"-";
+        let mut tester = GdbServer::tester(&mut input);
+        tester.set_max_retransmits(0);
+        match tester.reply_unsupported() {
+            Err(Error::RetransmitLimitExceeded) => (),
+            result => panic!("Expected error RetransmitLimitExceeded, got {:?}", result),
+        }
+        assert_eq!(tester.unsupported_replies(), 0);
+    }
+    #[test]
+    fn it_resends() {
+        let mut input: &[u8] = b"-+";
+        let mut tester = GdbServer::tester(&mut input);
+        tester.dispatch(&CheckedPacket::from_data(Kind::Packet, b"IMBATMAN".to_vec())).unwrap();
+        assert_eq!(tester.response(), b"$IMBATMAN#49$IMBATMAN#49");
+    }
+    #[test]
+    fn it_limits_retransmits() {
+        let mut input: &[u8] = b"$packet#99$packet#99$packet#99";
+        let mut tester = GdbServer::tester(&mut input);
+        tester.set_max_retransmits(2);
+        match tester.next_packet() {
+            Err(Error::RetransmitLimitExceeded) => (),
+            result => panic!("Expected error RetransmitLimitExceeded, got {:?}", result),
+        }
+        assert_eq!(tester.response(), b"--");
+
+        let mut input: &[u8] = b"----";
+        let mut tester = GdbServer::tester(&mut input);
+        match tester.dispatch(&ok_reply()) {
+            Err(Error::RetransmitLimitExceeded) => (),
+            result => panic!("Expected error RetransmitLimitExceeded, got {:?}", result),
+        }
+        assert_eq!(tester.response(), b"$OK#9a$OK#9a$OK#9a$OK#9a");
+    }
+    #[test]
+    fn it_coalesces_acknowledgments() {
+        #[derive(Default)]
+        struct CountingWriter(Vec<Vec<u8>>);
+        impl Write for CountingWriter {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.push(buf.to_vec());
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut input: &[u8] = b"$packet#78+$packet#78";
+        let mut server = GdbServer::new(&mut input, CountingWriter::default());
+        server.set_coalesce_ack(true);
+
+        server.next_packet().unwrap();
+        assert!(server.writer.0.is_empty());
+        server.dispatch(&crate::commands::ok_reply()).unwrap();
+        assert_eq!(server.writer.0, vec![b"+$OK#9a".to_vec()]);
+
+        // Without a reply, the acknowledgment is sent before reading on
+        server.writer.0.clear();
+        server.next_packet().unwrap();
+        assert_eq!(server.next_packet().unwrap(), None);
+        assert_eq!(server.writer.0, vec![b"+".to_vec()]);
+    }
+    #[test]
+    fn it_complains_when_the_user_lies() {
+        let mut input: &[u8] = b"-";
+        let mut tester = GdbServer::tester(&mut input);
+        let result = tester.dispatch(&CheckedPacket::assume_checked(UncheckedPacket {
+            kind: Kind::Packet,
+            data: b"This sentence is false. (dontthinkaboutitdontthinkaboutit)".to_vec(),
+            checksum: *b"FF",
+        }));
+        if let Err(Error::InvalidChecksum) = result {
+        } else {
+            panic!("Expected error InvalidChecksum, got {:?}", result);
+        }
+        // It will still send once, just in case the user has disabled checksum verification
+        assert_eq!(tester.response(), b"$This sentence is false. (dontthinkaboutitdontthinkaboutit)#FF".to_vec());
+    }
+    #[test]
+    fn it_limits_the_encoded_size() {
+        let mut input: &[u8] = b"";
+        let mut tester = GdbServer::tester(&mut input);
+        // Encodes to "$}]}]#fa", which is 8 bytes
+        let packet = CheckedPacket::from_data(Kind::Packet, b"}}".to_vec());
+
+        tester.set_max_send_size(7);
+        match tester.dispatch(&packet) {
+            Err(Error::PacketTooLarge { len: 8, max: 7, .. }) => (),
+            result => panic!("Expected error PacketTooLarge, got {:?}", result),
+        }
+        assert_eq!(tester.response(), b"");
+
+        tester.set_max_send_size(8);
+        tester.dispatch(&packet).unwrap();
+        assert_eq!(tester.response(), b"$}]}]#fa");
+    }
+    #[test]
+    fn it_acknowledges_stop_notifications() {
+        let mut tester =
+            GdbServer::tester_script(vec![&b"$vStopped#55"[..], b"+$vStopped#55", b"+"]);
+        tester.notify(b"T05".to_vec()).unwrap();
+        tester.notify(b"T06".to_vec()).unwrap();
+        assert_eq!(tester.response(), b"%Stop:T05#99");
+
+        let packet = tester.next_packet().unwrap().unwrap();
+        assert!(tester.notification_acked(&packet).unwrap());
+        // GDB acknowledged the reply, and sent the next packet
+        assert_eq!(tester.response(), b"+$T06#ba");
+        assert_eq!(tester.reader.remaining(), 2);
+
+        let packet = tester.next_packet().unwrap().unwrap();
+        assert!(tester.notification_acked(&packet).unwrap());
+        assert_eq!(tester.response(), b"+$OK#9a");
+
+        let packet = CheckedPacket::from_data(Kind::Packet, b"g".to_vec());
+        assert!(!tester.notification_acked(&packet).unwrap());
+        assert_eq!(tester.response(), b"");
+        assert_eq!(tester.next_packet().unwrap(), None);
+        assert_eq!(tester.reader.remaining(), 0);
+    }
+    #[test]
+    fn it_traces_raw_bytes() {
+        use std::sync::{Arc, Mutex};
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut input: &[u8] = b"$g#67+";
+        let mut tester = GdbServer::tester(&mut input);
+        let hook_log = Arc::clone(&log);
+        tester.set_trace(move |direction, bytes| {
+            hook_log.lock().unwrap().push((direction, bytes.to_vec()));
+        });
+
+        tester.next_packet().unwrap();
+        tester.dispatch(&ok_reply()).unwrap();
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                (Direction::In, b"$g#67".to_vec()),
+                (Direction::Out, b"+".to_vec()),
+                (Direction::Out, b"$OK#9a".to_vec()),
+                (Direction::In, b"+".to_vec()),
+            ]
+        );
+        assert_eq!(tester.response(), b"+$OK#9a");
+    }
+    #[test]
+    fn it_filters_packets() {
+        let mut input: &[u8] = b"$qTStatus#49+$qfThreadInfo#bb+$qTStatus#49+$g#67";
+        let mut tester = GdbServer::tester(&mut input);
+        assert_eq!(
+            tester
+                .next_packet_matching(|packet| packet.data == b"g")
+                .unwrap(),
+            Some(CheckedPacket::from_data(Kind::Packet, b"g".to_vec()))
+        );
+        assert_eq!(tester.response(), b"+$#00+$#00+$#00+");
+        assert_eq!(tester.unsupported_replies(), 3);
+        assert_eq!(tester.next_packet_matching(|_| true).unwrap(), None);
+    }
+    #[test]
+    fn it_erases_transport_types() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _addr) = listener.accept().unwrap();
+        let tcp = GdbServer::from_stream(stream).unwrap();
+
+        let mut servers: Vec<DynGdbServer> = vec![
+            GdbServer::boxed(tcp.reader, tcp.writer),
+            GdbServer::boxed(&b"$packet#78"[..], Vec::new()),
+        ];
+        client.write_all(b"$packet#78").unwrap();
+        for server in &mut servers {
+            assert_eq!(
+                server.next_packet().unwrap(),
+                Some(CheckedPacket::from_data(Kind::Packet, b"packet".to_vec()))
+            );
+        }
+        let mut ack = [0];
+        client.read_exact(&mut ack).unwrap();
+        assert_eq!(&ack, b"+");
+    }
+    #[test]
+    fn it_uses_custom_ack_bytes() {
+        let mut input: &[u8] = b"$packet#78$packet#99$packet#78";
+        let mut tester = GdbServer::tester(&mut input);
+        tester.set_ack_bytes(b'y', b'n');
+        tester.next_packet().unwrap();
+        tester.next_packet().unwrap();
+        assert_eq!(tester.response(), b"yny");
+
+        let mut input: &[u8] = b"ny";
+        let mut tester = GdbServer::tester(&mut input);
+        tester.set_ack_bytes(b'y', b'n');
+        tester.dispatch(&ok_reply()).unwrap();
+        assert_eq!(tester.response(), b"$OK#9a$OK#9a");
+    }
+    #[test]
+    fn it_queues_packets_received_while_awaiting_an_ack() {
+        let mut input: &[u8] = b"$g#67+$m0,4#fd";
+        let mut tester = GdbServer::tester(&mut input);
+        tester.dispatch(&ok_reply()).unwrap();
+        assert_eq!(tester.response(), b"$OK#9a+");
+        assert_eq!(
+            tester.next_packet().unwrap(),
+            Some(CheckedPacket::from_data(Kind::Packet, b"g".to_vec()))
+        );
+        assert_eq!(tester.response(), b"");
+        assert_eq!(tester.next_packet().unwrap().unwrap().data, b"m0,4");
+        assert_eq!(tester.response(), b"+");
+    }
+    #[test]
+    fn it_stops_awaiting_acks_once_packets_arrive() {
+        struct NoAck;
+        impl AckPolicy for NoAck {
+            fn on_packet(&mut self, _result: CheckResult) -> AckAction {
+                AckAction::None
+            }
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (stream, _addr) = listener.accept().unwrap();
+            let mut server = GdbServer::from_stream(stream).unwrap();
+            server.set_ack_policy(Box::new(NoAck));
+            while let Some(packet) = server.next_packet().unwrap() {
+                let mut reply = b"re:".to_vec();
+                reply.extend_from_slice(&packet.data);
+                server
+                    .dispatch(&CheckedPacket::from_data(Kind::Packet, reply))
+                    .unwrap();
+            }
+        });
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        client
+            .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+            .unwrap();
+        let mut reply = [0; 11];
+        client.write_all(b"$g#67").unwrap();
+        client.read_exact(&mut reply[..8]).unwrap();
+        assert_eq!(&reply[..8], b"$re:g#78");
+        // Send the next request without acknowledging the reply
+        client.write_all(b"$m0,4#fd").unwrap();
+        client.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply[..], b"$re:m0,4#0e");
+        drop(client);
+        server.join().unwrap();
+    }
+    #[test]
+    fn it_pairs_requests_with_replies() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (stream, _addr) = listener.accept().unwrap();
+            let mut server = GdbServer::from_stream(stream).unwrap();
+            while let Some(packet) = server.next_packet().unwrap() {
+                let mut reply = b"re:".to_vec();
+                reply.extend_from_slice(&packet.data);
+                server
+                    .dispatch(&CheckedPacket::from_data(Kind::Packet, reply))
+                    .unwrap();
+            }
+        });
+
+        let mut client = GdbClient::connect(addr).unwrap();
+        for &request in &[&b"g"[..], b"m0,4", b"qC"] {
+            let request = CheckedPacket::from_data(Kind::Packet, request.to_vec());
+            let reply = client.send_and_recv(&request).unwrap();
+            assert_eq!(reply.data, [&b"re:"[..], &request.data].concat());
+            assert_eq!(client.outstanding(), None);
+        }
+        drop(client);
+        server.join().unwrap();
+    }
+    #[test]
+    fn it_recovers_replies_missing_their_start() {
+        let request = CheckedPacket::from_data(Kind::Packet, b"g".to_vec());
+
+        let input: &[u8] = b"+OK#9a";
+        let mut client = GdbClient::new(input, Vec::new());
+        client.parser_mut().set_implicit_start(true);
+        assert_eq!(client.send_and_recv(&request).unwrap(), ok_reply());
+        assert_eq!(client.inner.writer, b"$g#67+");
+
+        // Whitespace after the ack doesn't hide a reply which has a start
+        let input: &[u8] = b"+\n$OK#9a";
+        let mut client = GdbClient::new(input, Vec::new());
+        client.parser_mut().set_implicit_start(true);
+        assert_eq!(client.send_and_recv(&request).unwrap(), ok_reply());
+
+        // Without it, the reply is skipped as garbage
+        let input: &[u8] = b"+OK#9a";
+        let mut client = GdbClient::new(input, Vec::new());
+        match client.send_and_recv(&request) {
+            Err(Error::Disconnected) => (),
+            result => panic!("Expected error Disconnected, got {:?}", result),
+        }
+    }
+    #[test]
+    fn it_keeps_replies_to_earlier_requests() {
+        let packet = |data: &[u8]| CheckedPacket::from_data(Kind::Packet, data.to_vec());
+
+        let input: &[u8] = b"+++$QC1#c5%Stop:T05#99$00#60";
+        let mut client = GdbClient::new(input, Vec::new());
+        client.send(&packet(b"R00")).unwrap();
+        assert_eq!(client.outstanding(), None);
+        client.send(&packet(b"qC")).unwrap();
+        assert_eq!(client.send_and_recv(&packet(b"g")).unwrap(), packet(b"00"));
+        assert_eq!(client.outstanding(), None);
+        assert_eq!(client.next_packet().unwrap(), Some(packet(b"QC1")));
+        assert_eq!(client.next_packet().unwrap().unwrap().data, b"Stop:T05");
+        assert_eq!(client.next_packet().unwrap(), None);
+
+        match client.send_and_recv(&packet(b"k")) {
+            Err(Error::Malformed(data)) => assert_eq!(data, b"k"),
+            result => panic!("Expected error Malformed, got {:?}", result),
+        }
+    }
+    #[test]
+    fn it_keeps_console_output_apart_from_replies() {
+        use crate::commands::MonitorCommand;
+
+        let mut input = b"+".to_vec();
+        for packet in MonitorCommand::reply(b"hi\n") {
+            packet.encode(&mut input).unwrap();
+        }
+        input.extend_from_slice(b"+$00#60");
+        let mut client = GdbClient::new(&input[..], Vec::new());
+        let request = CheckedPacket::from_data(Kind::Packet, b"qRcmd,6869".to_vec());
+        assert_eq!(client.send_and_recv(&request).unwrap(), ok_reply());
+        let request = CheckedPacket::from_data(Kind::Packet, b"g".to_vec());
+        assert_eq!(client.send_and_recv(&request).unwrap().data, b"00");
+        assert_eq!(client.next_packet().unwrap().unwrap().data, b"O68690a");
+        assert_eq!(client.outstanding(), None);
+    }
+    #[test]
+    fn it_verifies_checksums_with_the_parser_algorithm() {
+        use crate::packet::Checksum;
+
+        #[derive(Clone, Default)]
+        struct Xor(u8);
+        impl Checksum for Xor {
+            fn update(&mut self, bytes: &[u8]) {
+                self.0 = bytes.iter().fold(self.0, |sum, b| sum ^ b);
+            }
+            fn finalize(self) -> u8 {
+                self.0
+            }
+        }
+
+        let mut input: &[u8] = b"$OK#9a$OK#04+$vStopped#3f";
+        let parser = Parser::<Xor>::with_checksum();
+        let mut server = GdbServer::with_parser(&mut input, Vec::new(), parser);
+        assert_eq!(server.next_packet().unwrap().unwrap().data, b"OK");
+        assert_eq!(server.writer, b"-+");
+        server.writer.clear();
+
+        // Packets built by the server itself are summed the same way
+        server.notify(b"T05".to_vec()).unwrap();
+        assert_eq!(server.writer, b"%Stop:T05#53");
+        let packet = server.next_packet().unwrap().unwrap();
+        assert!(server.notification_acked(&packet).unwrap());
+        assert_eq!(server.writer, b"%Stop:T05#53+$OK#04");
+    }
+    #[test]
+    fn it_uses_custom_ack_policies() {
+        struct NeverDeny;
+        impl AckPolicy for NeverDeny {
+            fn on_packet(&mut self, result: CheckResult) -> AckAction {
+                match result {
+                    CheckResult::Valid(packet) if packet.kind == Kind::Packet => {
+                        AckAction::SendPositive
+                    }
+                    _ => AckAction::None,
+                }
+            }
+        }
+
+        let mut input: &[u8] = b"$packet#99$packet#78%notification#99";
+        let mut tester = GdbServer::tester(&mut input);
+        tester.set_ack_policy(Box::new(NeverDeny));
+        match tester.next_packet() {
+            Err(Error::ChecksumMismatch(packet)) => assert_eq!(packet.checksum, *b"99"),
+            result => panic!("Expected error ChecksumMismatch, got {:?}", result),
+        }
+        assert_eq!(tester.next_packet().unwrap().unwrap().data, b"packet");
+        assert_eq!(tester.next_packet().unwrap().unwrap().data, b"notification");
+        assert_eq!(tester.response(), b"+");
+    }
+    #[test]
+    fn it_verifies_notifications_on_request() {
+        let mut input: &[u8] = b"%Stop:T05#99%Stop:T05#12";
+        let mut tester = GdbServer::tester(&mut input);
+        assert_eq!(
+            tester.next_packet().unwrap(),
+            Some(CheckedPacket::assume_checked(UncheckedPacket {
+                kind: Kind::Notification,
+                data: b"Stop:T05".to_vec(),
+                checksum: *b"99",
+            }))
+        );
+        tester.set_verify_notifications(true);
+        match tester.next_packet() {
+            Err(Error::ChecksumMismatch(packet)) => assert_eq!(packet.checksum, *b"12"),
+            result => panic!("Expected error ChecksumMismatch, got {:?}", result),
+        }
+        assert_eq!(tester.next_packet().unwrap(), None);
+        assert_eq!(tester.response(), b"");
+    }
+    #[test]
+    fn it_takes_responses_of_any_reader() {
+        let reader = BufReader::new(std::io::Cursor::new(b"$packet#78".to_vec()));
+        let mut server = GdbServer::new(reader, Vec::new());
+        assert_eq!(server.next_packet().unwrap().unwrap().data, b"packet");
+        assert_eq!(server.response(), b"+");
+        assert_eq!(server.response(), b"");
+    }
+    #[test]
+    fn it_parses_pushed_bytes() {
+        let mut channel = PacketChannel::default();
+        let mut packets = Vec::new();
+        for chunk in b"$g#67$packet#99$m0,4#fd%Stop:T05#99".chunks(5) {
+            channel.push_bytes(chunk);
+            while let Some(packet) = channel.try_next() {
+                packets.push(packet.unwrap().data.clone());
+            }
+        }
+        assert!(channel.try_next().is_none());
+        assert_eq!(packets, [&b"g"[..], b"m0,4", b"Stop:T05"]);
+        assert_eq!(channel.take_output(), b"+-+");
+        assert_eq!(channel.take_output(), b"");
+    }
+    #[test]
+    fn it_bounds_pushed_bytes() {
+        let mut channel = PacketChannel::with_capacity(Parser::default(), 8);
+        let mut input: &[u8] = b"$g#67$m0,4#fd$g#67";
+        assert_eq!(channel.push_bytes(input), 8);
+        assert_eq!(channel.push_bytes(&input[8..]), 0);
+        input = &input[8..];
+
+        let mut packets = Vec::new();
+        while !input.is_empty() {
+            input = &input[channel.push_bytes(input)..];
+            while let Some(packet) = channel.try_next() {
+                packets.push(packet.unwrap().data.clone());
+            }
+        }
+        assert_eq!(packets, [&b"g"[..], b"m0,4", b"g"]);
+    }
+    #[test]
+    fn it_recovers_from_errors_in_pushed_bytes() {
+        let mut parser = Parser::default();
+        parser.set_strict(true);
+        let mut channel = PacketChannel::new(parser);
+        channel.push_bytes(b"x$g#67");
+        match channel.try_next() {
+            Some(Err(Error::UnexpectedByte(b'x'))) => (),
+            result => panic!("Expected error UnexpectedByte, got {:?}", result),
+        }
+        assert_eq!(channel.try_next().unwrap().unwrap().data, b"g");
+        assert!(channel.try_next().is_none());
+    }
+    #[test]
+    fn it_injects_bad_checksums() {
+        let mut input: &[u8] = b"-+";
+        let mut tester = GdbServer::tester(&mut input);
+        assert!(tester.dispatch_with_bad_checksum(&ok_reply()).unwrap());
+        let response = tester.response();
+        assert_eq!(response, b"$OK#65$OK#9a");
+
+        let packets = crate::parser::parse_all(&response).unwrap();
+        assert!(!packets[0].is_valid());
+        assert_eq!(packets[1].clone().check(), Some(ok_reply()));
+
+        let mut input: &[u8] = b"+";
+        let mut tester = GdbServer::tester(&mut input);
+        assert!(!tester.dispatch_with_bad_checksum(&ok_reply()).unwrap());
+        assert_eq!(tester.response(), b"$OK#65");
+    }
+    #[test]
+    fn it_tunnels_packets_through_messages() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let packets = [
+            ok_reply(),
+            CheckedPacket::from_data(Kind::Packet, b"m0,4:#$}*".to_vec()),
+            CheckedPacket::notification(b"Stop:T05".to_vec()),
+        ];
+        for packet in &packets {
+            sender.send(FramedTunnel::to_message(packet)).unwrap();
+        }
+        drop(sender);
+
+        let messages: Vec<Vec<u8>> = receiver.iter().collect();
+        assert_eq!(messages[0], b"$OK#9a");
+        for (message, packet) in messages.iter().zip(&packets) {
+            assert_eq!(&FramedTunnel::from_message(message).unwrap(), packet);
+        }
+
+        match FramedTunnel::from_message(b"$OK#9a+") {
+            Err(Error::Malformed(rest)) => assert_eq!(rest, b"+"),
+            result => panic!("Expected error Malformed, got {:?}", result),
+        }
+        match FramedTunnel::from_message(b"$OK#9") {
+            Err(Error::UnexpectedEof) => (),
+            result => panic!("Expected error UnexpectedEof, got {:?}", result),
+        }
+        match FramedTunnel::from_message(b"$OK#00") {
+            Err(Error::ChecksumMismatch(_)) => (),
+            result => panic!("Expected error ChecksumMismatch, got {:?}", result),
+        }
+    }
+    #[test]
+    fn it_enters_non_stop_mode() {
+        use crate::commands::{self, Command};
+
+        let mut input: &[u8] = b"$QNonStop:1#8d";
+        let mut tester = GdbServer::tester(&mut input);
+        assert!(!tester.is_non_stop());
+        let packet = tester.next_packet().unwrap().unwrap();
+        if let Some(Command::SetNonStop { enable }) = commands::parse(&packet).unwrap() {
+            tester.set_non_stop(enable);
+            tester.dispatch(&ok_reply()).unwrap();
+        }
+        assert!(tester.is_non_stop());
+        assert_eq!(tester.response(), b"+$OK#9a");
+    }
+    #[test]
+    fn it_serves_any_transport() {
+        fn echo<T: Transport>(transport: T) -> GdbServer<T::Reader, T::Writer> {
+            let mut server = GdbServer::serve(transport).unwrap();
+            while let Some(packet) = server.next_packet().unwrap() {
+                server.dispatch(&packet).unwrap();
+            }
+            server
+        }
+
+        let mock: (&[u8], _) = (b"$packet#78+", Vec::new());
+        assert_eq!(mock.peer(), None);
+        assert_eq!(echo(mock).writer, b"+$packet#78");
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _addr) = listener.accept().unwrap();
+        let addr = client.local_addr().unwrap();
+        assert_eq!(stream.peer(), Some(addr.to_string()));
+        let server = std::thread::spawn(move || echo(stream));
+
+        client.write_all(b"$packet#78").unwrap();
+        let mut reply = [0; 11];
+        client.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"+$packet#78");
+        client.write_all(b"+").unwrap();
+        client.shutdown(std::net::Shutdown::Write).unwrap();
+        assert!(!server.join().unwrap().is_connected());
+    }
+    #[test]
+    fn it_skips_duplicates_on_request() {
+        let mut input: &[u8] = b"$M0,1:ff#e0$M0,1:ff#e0+$M0,1:ff#e0";
+        let mut tester = GdbServer::tester(&mut input);
+        tester.set_dedup(true);
+        let mut handled = 0;
+        while let Some(packet) = tester.next_packet().unwrap() {
+            assert_eq!(packet.data, b"M0,1:ff");
+            handled += 1;
+            if handled == 1 {
+                tester.dispatch(&ok_reply()).unwrap();
+            }
+        }
+        // The packet following a reply is handled again
+        assert_eq!(handled, 2);
+        assert_eq!(tester.duplicates(), 1);
+        assert_eq!(tester.response(), b"+$OK#9a++");
+    }
+    #[test]
+    fn it_runs_handlers() {
+        let mut input: &[u8] = b"$packet#78+%Stop:T05#99$qTStatus#49+";
+        let mut tester = GdbServer::tester(&mut input);
+        let mut handled = Vec::new();
+        tester
+            .run(|packet| {
+                handled.push(packet.data.clone());
+                Some(packet.clone()).filter(|packet| packet.data == b"packet")
+            })
+            .unwrap();
+        assert_eq!(handled, [&b"packet"[..], b"Stop:T05", b"qTStatus"]);
+        assert_eq!(tester.response(), b"+$packet#78+$#00");
+        assert_eq!(tester.unsupported_replies(), 1);
+    }
+    #[test]
+    fn it_limits_session_bytes() {
+        let mut input: &[u8] = b"$g#67+$g#67+$g#67+";
+        let mut tester = GdbServer::tester(&mut input);
+        tester.set_session_byte_limit(10);
+
+        // The second packet crosses the limit, so only the third fails
+        assert_eq!(tester.next_packet().unwrap().unwrap().data, b"g");
+        assert_eq!(tester.next_packet().unwrap().unwrap().data, b"g");
+        for _ in 0..2 {
+            match tester.next_packet() {
+                Err(Error::SessionLimitExceeded) => (),
+                result => panic!("Expected error SessionLimitExceeded, got {:?}", result),
+            }
+        }
+
+        let mut input: &[u8] = b"$g#67+$g#67+$g#67+";
+        let mut tester = GdbServer::tester(&mut input);
+        for _ in 0..3 {
+            assert!(tester.next_packet().unwrap().is_some());
+        }
+        assert!(tester.next_packet().unwrap().is_none());
+    }
+    #[test]
+    fn it_limits_session_bytes_outside_of_packets() {
+        let input = b"xxxx\x03".repeat(5);
+        let mut input = &input[..];
+        let mut tester = GdbServer::tester(&mut input);
+        tester.set_session_byte_limit(12);
+        for _ in 0..3 {
+            assert_eq!(tester.next_event().unwrap(), Some(Event::Interrupt));
+        }
+        match tester.next_event() {
+            Err(Error::SessionLimitExceeded) => (),
+            result => panic!("Expected error SessionLimitExceeded, got {:?}", result),
+        }
+
+        // Acknowledgments count too
+        let mut input: &[u8] = b"++++$g#67";
+        let mut tester = GdbServer::tester(&mut input);
+        tester.set_session_byte_limit(2);
+        for _ in 0..3 {
+            tester.dispatch(&ok_reply()).unwrap();
+        }
+        match tester.next_packet() {
+            Err(Error::SessionLimitExceeded) => (),
+            result => panic!("Expected error SessionLimitExceeded, got {:?}", result),
         }
-        // It will still send once, just in case the user has disabled checksum verification
-        assert_eq!(tester.response(), b"$This sentence is false. (dontthinkaboutitdontthinkaboutit)#FF".to_vec());
     }
 }
@@ -3,25 +3,113 @@
 
 #![cfg_attr(feature = "unstable", feature(non_exhaustive))]
 
+use crate::packet::{Kind, UncheckedPacket};
+
 use std::fmt;
 
+#[cfg(feature = "tokio")]
+pub mod async_io;
+pub mod commands;
 pub mod io;
 pub mod packet;
 pub mod parser;
+#[cfg(feature = "testing")]
+pub mod testing;
 
 #[derive(Debug)]
 #[cfg_attr(feature = "unstable", non_exhaustive)]
 pub enum Error {
+    ChecksumMismatch(UncheckedPacket),
+    Disconnected,
     InvalidChecksum,
+    InvalidChecksumDigit(u8),
     IoError(std::io::Error),
+    Malformed(Vec<u8>),
     NonNumber(String, std::num::ParseIntError),
     NonUtf8(Vec<u8>, std::str::Utf8Error),
+    PacketTooLarge { kind: Kind, len: usize, max: usize },
+    RetransmitLimitExceeded,
+    SessionLimitExceeded,
+    TrailingChecksumByte(u8),
+    UnexpectedByte(u8),
+    UnexpectedEof,
+}
+impl Error {
+    pub fn non_number(string: impl Into<String>, err: std::num::ParseIntError) -> Self {
+        Error::NonNumber(string.into(), err)
+    }
+    pub fn non_utf8(bytes: impl Into<Vec<u8>>, err: std::str::Utf8Error) -> Self {
+        Error::NonUtf8(bytes.into(), err)
+    }
+    /// Return a cloneable snapshot of this error. Inner errors that
+    /// can't be cloned are replaced by their message, and for
+    /// `IoError` by their `std::io::ErrorKind` as well.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::ChecksumMismatch(packet) => ErrorKind::ChecksumMismatch(packet.clone()),
+            Error::Disconnected => ErrorKind::Disconnected,
+            Error::InvalidChecksum => ErrorKind::InvalidChecksum,
+            Error::InvalidChecksumDigit(b) => ErrorKind::InvalidChecksumDigit(*b),
+            Error::IoError(err) => ErrorKind::IoError(err.kind(), err.to_string()),
+            Error::Malformed(bytes) => ErrorKind::Malformed(bytes.clone()),
+            Error::NonNumber(string, err) => ErrorKind::NonNumber(string.clone(), err.to_string()),
+            Error::NonUtf8(bytes, err) => ErrorKind::NonUtf8(bytes.clone(), err.to_string()),
+            Error::PacketTooLarge { kind, len, max } => ErrorKind::PacketTooLarge {
+                kind: *kind,
+                len: *len,
+                max: *max,
+            },
+            Error::RetransmitLimitExceeded => ErrorKind::RetransmitLimitExceeded,
+            Error::SessionLimitExceeded => ErrorKind::SessionLimitExceeded,
+            Error::TrailingChecksumByte(b) => ErrorKind::TrailingChecksumByte(*b),
+            Error::UnexpectedByte(b) => ErrorKind::UnexpectedByte(*b),
+            Error::UnexpectedEof => ErrorKind::UnexpectedEof,
+        }
+    }
+}
+
+/// A cloneable projection of `Error`, as returned by `Error::kind`.
+/// The variants mirror those of `Error`, with non-cloneable inner
+/// errors stringified.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "unstable", non_exhaustive)]
+pub enum ErrorKind {
+    ChecksumMismatch(UncheckedPacket),
+    Disconnected,
+    InvalidChecksum,
+    InvalidChecksumDigit(u8),
+    IoError(std::io::ErrorKind, String),
+    Malformed(Vec<u8>),
+    NonNumber(String, String),
+    NonUtf8(Vec<u8>, String),
+    PacketTooLarge { kind: Kind, len: usize, max: usize },
+    RetransmitLimitExceeded,
+    SessionLimitExceeded,
+    TrailingChecksumByte(u8),
+    UnexpectedByte(u8),
+    UnexpectedEof,
 }
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
+            Error::ChecksumMismatch(packet) => write!(
+                f,
+                "received a packet with checksum {:?} not matching its data",
+                String::from_utf8_lossy(&packet.checksum)
+            ),
+            Error::Disconnected => write!(f, "the peer has disconnected"),
             Error::InvalidChecksum => write!(f, "a packet with invalid checksum was sent and denied"),
+            Error::InvalidChecksumDigit(b) => write!(
+                f,
+                "expected hexadecimal checksum digit, found {:?}",
+                *b as char
+            ),
             Error::IoError(err) => write!(f, "i/o error: {}", err),
+            Error::Malformed(bytes) => write!(
+                f,
+                "malformed packet data: {:?}",
+                String::from_utf8_lossy(bytes)
+            ),
             Error::NonNumber(string, err) => {
                 write!(f, "expected number, found {:?}: {}", string, err)
             }
@@ -30,6 +118,24 @@ impl fmt::Display for Error {
                 "expected UTF-8 string in this context, found {:?}: {}",
                 bytes, err
             ),
+            Error::PacketTooLarge { kind, len, max } => write!(
+                f,
+                "{} of {} bytes exceeds the maximum size of {} bytes",
+                kind, len, max
+            ),
+            Error::RetransmitLimitExceeded => {
+                write!(f, "gave up on a packet after too many retransmits")
+            }
+            Error::SessionLimitExceeded => {
+                write!(f, "the peer sent more than the limit of bytes per session")
+            }
+            Error::TrailingChecksumByte(b) => {
+                write!(f, "unexpected byte {:?} right after a checksum", *b as char)
+            }
+            Error::UnexpectedByte(b) => {
+                write!(f, "unexpected byte {:?} outside of a packet", *b as char)
+            }
+            Error::UnexpectedEof => write!(f, "unexpected end of input in the middle of a packet"),
         }
     }
 }
@@ -48,3 +154,37 @@ impl From<std::io::Error> for Error {
         Error::IoError(err)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_projects_errors_to_kinds() {
+        let io = Error::from(std::io::Error::new(std::io::ErrorKind::BrokenPipe, "gone"));
+        let number = Error::non_number("xyz", u8::from_str_radix("xyz", 16).unwrap_err());
+        let invalid = vec![0xFF];
+        let utf8 = Error::non_utf8(invalid.clone(), std::str::from_utf8(&invalid).unwrap_err());
+
+        match io.kind() {
+            ErrorKind::IoError(std::io::ErrorKind::BrokenPipe, message) => {
+                assert_eq!(message, "gone")
+            }
+            kind => panic!("Expected kind IoError, got {:?}", kind),
+        }
+        match number.kind() {
+            ErrorKind::NonNumber(string, _) => assert_eq!(string, "xyz"),
+            kind => panic!("Expected kind NonNumber, got {:?}", kind),
+        }
+        match utf8.kind() {
+            ErrorKind::NonUtf8(bytes, _) => assert_eq!(bytes, [0xFF]),
+            kind => panic!("Expected kind NonUtf8, got {:?}", kind),
+        }
+
+        let kinds = [io.kind(), number.kind(), utf8.kind()];
+        assert_ne!(kinds[0], kinds[1]);
+        assert_ne!(kinds[1], kinds[2]);
+        assert_ne!(kinds[0], kinds[2]);
+        assert_eq!(kinds.clone(), kinds);
+    }
+}
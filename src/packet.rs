@@ -1,12 +1,157 @@
-use crate::{parser::CHECKSUM_LEN, Error};
+use crate::{commands, parser::CHECKSUM_LEN, Error};
 
-use std::{cmp, io::{self, prelude::*}, ops::Deref};
+use std::{borrow::Cow, cmp, fmt, io::{self, prelude::*}, iter, ops::Deref};
 
+/// Creates a `CheckedPacket` of the specified kind from a format
+/// string, see `CheckedPacket::format`.
+#[macro_export]
+macro_rules! packet {
+    ($kind:expr, $($arg:tt)*) => {
+        $crate::packet::CheckedPacket::format($kind, format_args!($($arg)*))
+    };
+}
+
+/// A checksum algorithm, fed the packet data in one or more chunks.
+pub trait Checksum: Clone + Default {
+    fn update(&mut self, bytes: &[u8]);
+    fn finalize(self) -> u8;
+}
+
+/// The checksum specified by GDB: the sum of all bytes, modulo 256.
+///
+/// ```rust
+/// # use gdb_protocol::packet::{Checksum, Gdb8BitSum};
+/// # fn test(input: &str) {
+/// let mut whole = Gdb8BitSum::default();
+/// whole.update(input.as_bytes());
+///
+/// let mut chunked = Gdb8BitSum::default();
+/// for chunk in input.as_bytes().chunks(3) {
+///     chunked.update(chunk);
+/// }
+///
+/// let reference = (input.bytes().map(|x| usize::from(x)).sum::<usize>() % 256) as u8;
+/// assert_eq!(whole.finalize(), reference);
+/// assert_eq!(chunked.finalize(), reference);
+/// # }
+/// # test("");
+/// # test("Hello, World!");
+/// # test("Why so serious?");
+/// # test("\x7f\u{ff}\u{fe}}}}}}}}}}}}}}}}}}}}}}}}}}}}}}}}}}}");
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Gdb8BitSum(u8);
+impl Checksum for Gdb8BitSum {
+    fn update(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = self.0.wrapping_add(b);
+        }
+    }
+    fn finalize(self) -> u8 {
+        self.0
+    }
+}
+
+/// Returns the GDB checksum of `data`, without needing a packet.
+///
+/// ```rust
+/// # use gdb_protocol::packet::checksum;
+/// # fn test(input: &str) {
+/// let reference = (input.bytes().map(|x| usize::from(x)).sum::<usize>() % 256) as u8;
+/// assert_eq!(checksum(input.as_bytes()), reference);
+/// # }
+/// # test("");
+/// # test("OK");
+/// # test("qSupported:multiprocess+;swbreak+;hwbreak+");
+/// # test("Pull the lever, Kronk!");
+/// # test("\x7f\u{ff}\u{fe}}}}}}}}}}}}}}}}}}}}}}}}}}}}}}}}}}}");
+/// ```
+pub fn checksum(data: &[u8]) -> u8 {
+    let mut sum = Gdb8BitSum::default();
+    sum.update(data);
+    sum.finalize()
+}
+
+/// Whether a packet is a regular packet or a notification, displayed
+/// the way the specification calls them:
+///
+/// ```rust
+/// # use gdb_protocol::packet::Kind;
+/// assert_eq!(Kind::Packet.to_string(), "packet");
+/// assert_eq!(Kind::Notification.to_string(), "notification");
+/// ```
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Kind {
     Notification, // %
     Packet,       // $
 }
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Kind::Notification => "notification",
+            Kind::Packet => "packet",
+        })
+    }
+}
+
+/// The case of the hexadecimal digits of a generated checksum. GDB
+/// itself uses lowercase, which is the default.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum HexCase {
+    #[default]
+    Lower,
+    Upper,
+}
+
+/// Returns true if the byte must be escaped when encoded.
+pub(crate) fn needs_escape(b: u8) -> bool {
+    b == b'#' || b == b'$' || b == b'}' || b == b'*'
+}
+/// Returns the position of the first byte which must be escaped.
+fn find_escape(data: &[u8]) -> Option<usize> {
+    let escape1 = memchr::memchr3(b'#', b'$', b'}', data);
+    let escape2 = memchr::memchr(b'*', data);
+    match (escape1, escape2) {
+        (Some(escape1), Some(escape2)) => Some(cmp::min(escape1, escape2)),
+        (escape1, escape2) => escape1.or(escape2),
+    }
+}
+
+/// Returns the escape sequence of a byte which `needs_escape`.
+fn escape_sequence(b: u8) -> &'static [u8] {
+    match b {
+        b'#' => b"}\x03",
+        b'$' => b"}\x04",
+        b'}' => b"}]",
+        b'*' => b"}\x0a",
+        _ => unreachable!("byte {:?} doesn't need to be escaped", b),
+    }
+}
+
+/// Returns the position and value of the first byte of `data` which
+/// has a meaning in the framing of packets (`#`, `$`, `}` or `*`), if
+/// any. Encoding packets escapes these bytes regardless, so this is
+/// only meant to catch bugs in code which expects its data to be
+/// escaped already.
+///
+/// ```rust
+/// # use gdb_protocol::packet::contains_raw_framing;
+/// assert_eq!(contains_raw_framing(b"qRcmd,$reset"), Some((6, b'$')));
+/// assert_eq!(contains_raw_framing(b"qRcmd,7265736574"), None);
+/// ```
+pub fn contains_raw_framing(data: &[u8]) -> Option<(usize, u8)> {
+    find_escape(data).map(|pos| (pos, data[pos]))
+}
+
+fn hex_digit(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct UncheckedPacket {
@@ -15,6 +160,33 @@ pub struct UncheckedPacket {
     pub checksum: [u8; CHECKSUM_LEN as usize],
 }
 impl UncheckedPacket {
+    /// Start building a packet, as an alternative to the struct
+    /// literal. Unless changed, the packet is a `Kind::Packet` with no
+    /// data and a checksum of `00`.
+    ///
+    /// ```rust
+    /// # use gdb_protocol::packet::{CheckedPacket, Kind, UncheckedPacket};
+    /// assert_eq!(
+    ///     UncheckedPacket::builder()
+    ///         .kind(Kind::Notification)
+    ///         .data("Stop:T05")
+    ///         .checksum(*b"99")
+    ///         .build(),
+    ///     UncheckedPacket {
+    ///         kind: Kind::Notification,
+    ///         data: b"Stop:T05".to_vec(),
+    ///         checksum: *b"99",
+    ///     },
+    /// );
+    /// assert_eq!(
+    ///     UncheckedPacket::builder().data("Hello, World!").build_checked(),
+    ///     CheckedPacket::from_data(Kind::Packet, b"Hello, World!".to_vec()),
+    /// );
+    /// ```
+    pub fn builder() -> PacketBuilder {
+        PacketBuilder::default()
+    }
+
     /// Return the integer parsed from the hexadecimal expected
     /// checksum.
     ///
@@ -26,11 +198,22 @@ impl UncheckedPacket {
     ///     checksum: *b"BA",
     /// };
     /// assert_eq!(packet.expected_checksum().unwrap(), 186);
+    /// # for (checksum, expected) in &[(*b"00", 0), (*b"ff", 255), (*b"fF", 255), (*b"7a", 122)] {
+    /// #     let packet = UncheckedPacket { checksum: *checksum, ..packet.clone() };
+    /// #     assert_eq!(packet.expected_checksum().unwrap(), *expected);
+    /// # }
+    /// # for checksum in &[*b"0g", *b"g0", *b"  ", *b"\xff0", *b"-1"] {
+    /// #     let packet = UncheckedPacket { checksum: *checksum, ..packet.clone() };
+    /// #     assert!(packet.expected_checksum().is_err());
+    /// # }
     /// ```
     pub fn expected_checksum(&self) -> Result<u8, Error> {
-        let string = std::str::from_utf8(&self.checksum)
-            .map_err(|err| Error::NonUtf8(self.checksum.to_vec(), err))?;
-        u8::from_str_radix(string, 16).map_err(|err| Error::NonNumber(string.to_owned(), err))
+        // This runs for every received packet, so skip the UTF-8
+        // validation and generic integer parsing for valid checksums
+        match (hex_digit(self.checksum[0]), hex_digit(self.checksum[1])) {
+            (Some(high), Some(low)) => Ok(high << 4 | low),
+            _ => commands::hex_number(&self.checksum, u8::from_str_radix),
+        }
     }
 
     /// Return the actual checksum, derived from the data.
@@ -70,11 +253,14 @@ impl UncheckedPacket {
     /// however, this function is more efficient and won't go out of
     /// bounds.
     pub fn actual_checksum(&self) -> u8 {
-        let mut hash: u8 = 0;
-        for &b in &self.data {
-            hash = hash.wrapping_add(b);
-        }
-        hash
+        checksum(&self.data)
+    }
+    /// Return the actual checksum, derived from the data using a
+    /// checksum algorithm of choice.
+    pub fn actual_checksum_with<C: Checksum>(&self) -> u8 {
+        let mut checksum = C::default();
+        checksum.update(&self.data);
+        checksum.finalize()
     }
 
     /// Returns true if the checksums match.
@@ -102,8 +288,10 @@ impl UncheckedPacket {
     }
 
     /// Will return a checked packet if, and only if, the checksums
-    /// match. If you know the packet wasn't corrupted and want to
-    /// bypass the check, use `CheckedPacket::assume_checked`.
+    /// match. The expected checksum may be in either case, regardless
+    /// of the `HexCase` used by the peer. If you know the packet
+    /// wasn't corrupted and want to bypass the check, use
+    /// `CheckedPacket::assume_checked`.
     pub fn check(self) -> Option<CheckedPacket> {
         if self.is_valid() {
             Some(CheckedPacket::assume_checked(self))
@@ -112,6 +300,52 @@ impl UncheckedPacket {
         }
     }
 
+    /// Return a human-readable rendering of the packet, for logging.
+    /// Printable ASCII is shown as-is, while any other bytes are shown
+    /// as `\xNN`.
+    ///
+    /// ```rust
+    /// # use gdb_protocol::packet::{CheckedPacket, Kind};
+    /// let packet = CheckedPacket::from_data(Kind::Packet, b"X1000,2:\x00\xff\\".to_vec());
+    /// assert_eq!(packet.display().to_string(), r"$X1000,2:\x00\xff\\#0c");
+    /// ```
+    pub fn display(&self) -> impl fmt::Display + '_ {
+        PacketDisplay(self)
+    }
+    /// Return the data as a string, with invalid UTF-8 replaced by
+    /// `U+FFFD`, for logging. Unlike `display`, this doesn't show the
+    /// exact bytes.
+    ///
+    /// ```rust
+    /// # use gdb_protocol::packet::{CheckedPacket, Kind};
+    /// let packet = CheckedPacket::from_data(Kind::Packet, b"O\xffk".to_vec());
+    /// assert_eq!(packet.data_lossy(), "O\u{FFFD}k");
+    /// let packet = CheckedPacket::from_data(Kind::Packet, b"OK".to_vec());
+    /// assert_eq!(packet.data_lossy(), "OK");
+    /// ```
+    pub fn data_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.data)
+    }
+
+    /// Return the length of the encoded packet, including framing and
+    /// escaping overhead.
+    ///
+    /// ```rust
+    /// # use gdb_protocol::packet::{Kind, UncheckedPacket};
+    /// let packet = UncheckedPacket {
+    ///     kind: Kind::Packet,
+    ///     data: b"these must be escaped: # $ } *".to_vec(),
+    ///     checksum: *b"00",
+    /// };
+    /// let mut encoded = Vec::new();
+    /// packet.encode(&mut encoded).unwrap();
+    /// assert_eq!(packet.encoded_len(), encoded.len());
+    /// ```
+    pub fn encoded_len(&self) -> usize {
+        let escaped = self.data.iter().filter(|&&b| needs_escape(b)).count();
+        1 + self.data.len() + escaped + 1 + CHECKSUM_LEN as usize
+    }
+
     /// Encode the packet into a long binary string, written to a
     /// writer of choice. You can receive a Vec<u8> by taking
     /// advantage of the fact that they implement io::Write:
@@ -138,34 +372,215 @@ impl UncheckedPacket {
     where
         W: Write,
     {
-        w.write_all(&[match self.kind {
-            Kind::Notification => b'%',
-            Kind::Packet => b'$',
-        }])?;
-
-        let mut remaining: &[u8] = &self.data;
-        while !remaining.is_empty() {
-            let escape1 = memchr::memchr3(b'#', b'$', b'}', remaining);
-            let escape2 = memchr::memchr(b'*', remaining);
-
-            let escape = cmp::min(
-                escape1.unwrap_or_else(|| remaining.len()),
-                escape2.unwrap_or_else(|| remaining.len()),
-            );
-
-            w.write_all(&remaining[..escape])?;
-            remaining = &remaining[escape..];
-
-            if let Some(&b) = remaining.first() {
-                dbg!(b as char);
-                // memchr found a character that needs escaping, so let's do that
-                w.write_all(&[b'}', b ^ 0x20])?;
-                remaining = &remaining[1..];
+        write_kind(w, self.kind)?;
+        write_escaped(w, &self.data)?;
+        #[allow(clippy::byte_char_slices)]
+        w.write_all(&[b'#'])?;
+        w.write_all(&self.checksum)?;
+        Ok(())
+    }
+    /// Encode the packet in a canonical form, which unlike `encode` is
+    /// guaranteed to stay the same across versions: the kind, the data
+    /// with exactly the bytes `#`, `$`, `}` and `*` escaped and nothing
+    /// shortened, and the checksum as stored, even if it doesn't match.
+    /// This is meant for relays or caches which need a deterministic
+    /// encoding.
+    ///
+    /// ```rust
+    /// # use gdb_protocol::packet::{Kind, UncheckedPacket};
+    /// let mut encoded = Vec::new();
+    /// UncheckedPacket {
+    ///     kind: Kind::Notification,
+    ///     data: b"Stop:T05;aaaaaaaa#$}*".to_vec(),
+    ///     checksum: *b"Zz",
+    /// }.encode_verbatim(&mut encoded)?;
+    /// assert_eq!(encoded, b"%Stop:T05;aaaaaaaa}\x03}\x04}]}\x0a#Zz");
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn encode_verbatim<W>(&self, w: &mut W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        write_kind(w, self.kind)?;
+        write_escaped(w, &self.data)?;
+        w.write_all(b"#")?;
+        w.write_all(&self.checksum)?;
+        Ok(())
+    }
+    /// Return the encoded packet as a sequence of slices, which borrow
+    /// the data in between the bytes which must be escaped. This is
+    /// meant for vectored writes, avoiding copies of large packets. The
+    /// encoding is the canonical one of `encode_verbatim`.
+    ///
+    /// ```rust
+    /// # use gdb_protocol::packet::{CheckedPacket, Kind};
+    /// # use std::io::IoSlice;
+    /// let packet = CheckedPacket::from_data(Kind::Packet, b"X0,4:#$}*".to_vec());
+    /// let slices: Vec<IoSlice> = packet.encoded_chunks().map(IoSlice::new).collect();
+    /// assert_eq!(slices.len(), 8);
+    ///
+    /// let mut encoded = Vec::new();
+    /// packet.encode(&mut encoded)?;
+    /// assert_eq!(packet.encoded_chunks().collect::<Vec<_>>().concat(), encoded);
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn encoded_chunks(&self) -> impl Iterator<Item = &[u8]> + '_ {
+        let kind: &[u8] = match self.kind {
+            Kind::Notification => b"%",
+            Kind::Packet => b"$",
+        };
+        let mut remaining = &self.data[..];
+        let mut escape = None;
+        let data = iter::from_fn(move || {
+            if let Some(escape) = escape.take() {
+                return Some(escape);
             }
+            let pos = find_escape(remaining).unwrap_or(remaining.len());
+            let (run, rest) = remaining.split_at(pos);
+            remaining = match rest.split_first() {
+                Some((&b, rest)) => {
+                    escape = Some(escape_sequence(b));
+                    rest
+                }
+                None => rest,
+            };
+            match run {
+                b"" => escape.take(),
+                run => Some(run),
+            }
+        });
+        iter::once(kind)
+            .chain(data)
+            .chain(iter::once(&b"#"[..]))
+            .chain(iter::once(&self.checksum[..]))
+    }
+}
+
+fn write_kind<W: Write>(w: &mut W, kind: Kind) -> io::Result<()> {
+    w.write_all(&[match kind {
+        Kind::Notification => b'%',
+        Kind::Packet => b'$',
+    }])
+}
+/// Escapes exactly the bytes which must be escaped. Both `encode` and
+/// `encode_verbatim` rely on this, so any shortening belongs in the
+/// former.
+fn write_escaped<W: Write>(w: &mut W, mut remaining: &[u8]) -> io::Result<()> {
+    while !remaining.is_empty() {
+        let escape = find_escape(remaining).unwrap_or(remaining.len());
+
+        w.write_all(&remaining[..escape])?;
+        remaining = &remaining[escape..];
+
+        if let Some(&b) = remaining.first() {
+            // find_escape found a character that needs escaping, so let's do that
+            w.write_all(&[b'}', b ^ 0x20])?;
+            remaining = &remaining[1..];
         }
+    }
+    Ok(())
+}
 
-        w.write_all(&[b'#'])?;
-        w.write_all(&self.checksum)?;
+/// Encodes a packet as its data is generated, without keeping the
+/// data in memory. The data is escaped and summed while it is
+/// written, and the checksum is written by `finish`.
+///
+/// ```rust
+/// # use gdb_protocol::{packet::{Kind, StreamEncoder}, parser::parse_all};
+/// let data: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+///
+/// let mut encoder = StreamEncoder::new(Kind::Packet, Vec::new())?;
+/// for chunk in data.chunks(4096) {
+///     encoder.write_data(chunk)?;
+/// }
+/// let encoded = encoder.finish()?;
+///
+/// let packets = parse_all(&encoded).unwrap();
+/// assert_eq!(packets.len(), 1);
+/// assert!(packets[0].is_valid());
+/// assert_eq!(packets[0].data, data);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct StreamEncoder<W: Write> {
+    writer: W,
+    sum: Gdb8BitSum,
+}
+impl<W: Write> StreamEncoder<W> {
+    /// Starts a packet of the specified kind, writing its first byte.
+    pub fn new(kind: Kind, mut writer: W) -> io::Result<Self> {
+        write_kind(&mut writer, kind)?;
+        Ok(Self {
+            writer,
+            sum: Gdb8BitSum::default(),
+        })
+    }
+    /// Writes more data, escaping it as needed.
+    pub fn write_data(&mut self, data: &[u8]) -> io::Result<()> {
+        self.sum.update(data);
+        write_escaped(&mut self.writer, data)
+    }
+    /// Ends the packet by writing its checksum, returning the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        write!(self.writer, "#{:02x}", self.sum.finalize())?;
+        Ok(self.writer)
+    }
+}
+
+/// A builder for packets, see `UncheckedPacket::builder`.
+#[derive(Clone, Debug)]
+pub struct PacketBuilder {
+    packet: UncheckedPacket,
+}
+impl Default for PacketBuilder {
+    fn default() -> Self {
+        Self {
+            packet: UncheckedPacket {
+                kind: Kind::Packet,
+                data: Vec::new(),
+                checksum: *b"00",
+            },
+        }
+    }
+}
+impl PacketBuilder {
+    pub fn kind(mut self, kind: Kind) -> Self {
+        self.packet.kind = kind;
+        self
+    }
+    pub fn data(mut self, data: impl Into<Vec<u8>>) -> Self {
+        self.packet.data = data.into();
+        self
+    }
+    pub fn checksum(mut self, checksum: [u8; CHECKSUM_LEN as usize]) -> Self {
+        self.packet.checksum = checksum;
+        self
+    }
+    /// Build the packet, as is.
+    pub fn build(self) -> UncheckedPacket {
+        self.packet
+    }
+    /// Build the packet, generating the checksum from the data like
+    /// `CheckedPacket::from_data`. Any configured checksum is ignored.
+    pub fn build_checked(self) -> CheckedPacket {
+        CheckedPacket::from_data(self.packet.kind, self.packet.data)
+    }
+}
+
+struct PacketDisplay<'a>(&'a UncheckedPacket);
+impl fmt::Display for PacketDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let packet = self.0;
+        f.write_str(match packet.kind {
+            Kind::Notification => "%",
+            Kind::Packet => "$",
+        })?;
+        for &b in packet.data.iter().chain(b"#").chain(&packet.checksum) {
+            match b {
+                b'\\' => f.write_str("\\\\")?,
+                b' '..=b'~' => write!(f, "{}", b as char)?,
+                _ => write!(f, "\\x{:02x}", b)?,
+            }
+        }
         Ok(())
     }
 }
@@ -186,6 +601,32 @@ impl CheckedPacket {
     pub fn invalidate_check(self) -> UncheckedPacket {
         self.unchecked
     }
+    /// Verifies the checksum again, failing with
+    /// `Error::ChecksumMismatch` if the packet was created by
+    /// `assume_checked` from corrupt data. Packets checked any other
+    /// way always pass.
+    ///
+    /// ```rust
+    /// # use gdb_protocol::{Error, packet::{CheckedPacket, Kind, UncheckedPacket}};
+    /// assert!(CheckedPacket::from_data(Kind::Packet, b"OK".to_vec()).verify().is_ok());
+    ///
+    /// let packet = CheckedPacket::assume_checked(UncheckedPacket {
+    ///     kind: Kind::Packet,
+    ///     data: b"OK".to_vec(),
+    ///     checksum: *b"00",
+    /// });
+    /// match packet.verify() {
+    ///     Err(Error::ChecksumMismatch(unchecked)) => assert_eq!(unchecked, packet),
+    ///     result => panic!("Expected error ChecksumMismatch, got {:?}", result),
+    /// }
+    /// ```
+    pub fn verify(&self) -> Result<(), Error> {
+        if self.is_valid() {
+            Ok(())
+        } else {
+            Err(Error::ChecksumMismatch(self.unchecked.clone()))
+        }
+    }
 
     /// The empty packet is used when you get a packet which you just
     /// don't understand. Replying an empty packet means "I don't
@@ -206,7 +647,7 @@ impl CheckedPacket {
     }
 
     /// Creates a packet from the inputted binary data, and generates
-    /// the checksum from it.
+    /// the checksum from it, in lowercase like GDB does.
     /// ```rust
     /// # use gdb_protocol::packet::{CheckedPacket, Kind, UncheckedPacket};
     /// assert_eq!(
@@ -218,16 +659,192 @@ impl CheckedPacket {
     ///     },
     /// )
     /// ```
+    ///
+    /// Packets sent by GDB therefore round-trip byte-exactly:
+    ///
+    /// ```rust
+    /// # use gdb_protocol::{packet::{CheckedPacket, Kind}, parser::parse_all};
+    /// let sent = b"$qSupported:multiprocess+;swbreak+#1b";
+    /// let received = parse_all(sent).unwrap().remove(0);
+    /// let mut encoded = Vec::new();
+    /// CheckedPacket::from_data(Kind::Packet, received.data.clone())
+    ///     .encode(&mut encoded)
+    ///     .unwrap();
+    /// assert_eq!(encoded, &sent[..]);
+    /// ```
     pub fn from_data(kind: Kind, data: Vec<u8>) -> Self {
+        Self::from_data_with::<Gdb8BitSum>(kind, data)
+    }
+    /// Like `from_data`, but writes the checksum in the specified
+    /// case, for peers expecting an exact match.
+    ///
+    /// ```rust
+    /// # use gdb_protocol::packet::{CheckedPacket, HexCase, Kind};
+    /// let packet = CheckedPacket::from_data_with_case(Kind::Packet, b"OK".to_vec(), HexCase::Upper);
+    /// assert_eq!(packet.checksum, *b"9A");
+    /// let packet = CheckedPacket::from_data_with_case(Kind::Packet, b"OK".to_vec(), HexCase::Lower);
+    /// assert_eq!(packet.checksum, *b"9a");
+    /// ```
+    pub fn from_data_with_case(kind: Kind, data: Vec<u8>, case: HexCase) -> Self {
+        let mut packet = Self::from_data(kind, data).invalidate_check();
+        if case == HexCase::Upper {
+            packet.checksum.make_ascii_uppercase();
+        }
+        Self::assume_checked(packet)
+    }
+    /// Creates a packet from formatted text, generating the checksum
+    /// from it. The text is formatted directly into the data of the
+    /// packet. This is usually used through the `packet!` macro:
+    ///
+    /// ```rust
+    /// # use gdb_protocol::{packet, packet::{CheckedPacket, Kind}};
+    /// let (signal, thread) = (5, 0x1f);
+    /// assert_eq!(
+    ///     packet!(Kind::Packet, "T{:02x}thread:{:x};", signal, thread),
+    ///     CheckedPacket::from_data(Kind::Packet, b"T05thread:1f;".to_vec()),
+    /// );
+    /// ```
+    pub fn format(kind: Kind, args: fmt::Arguments) -> Self {
+        let mut data = Vec::new();
+        data.write_fmt(args)
+            .expect("writing to a Vec<u8> is infallible");
+        Self::from_data(kind, data)
+    }
+    /// Creates a notification packet from the inputted binary data,
+    /// and generates the checksum from it.
+    ///
+    /// ```rust
+    /// # use gdb_protocol::packet::{CheckedPacket, Kind};
+    /// let packet = CheckedPacket::notification(b"Stop:T05".to_vec());
+    /// assert_eq!(packet, CheckedPacket::from_data(Kind::Notification, b"Stop:T05".to_vec()));
+    ///
+    /// let mut encoded = Vec::new();
+    /// packet.encode(&mut encoded);
+    /// assert_eq!(encoded, b"%Stop:T05#99");
+    /// ```
+    pub fn notification(data: Vec<u8>) -> Self {
+        Self::from_data(Kind::Notification, data)
+    }
+    /// Like `from_data`, but generates the checksum using an
+    /// algorithm of choice, for protocol variants.
+    pub fn from_data_with<C: Checksum>(kind: Kind, data: Vec<u8>) -> Self {
         let mut packet = UncheckedPacket {
             kind,
             data,
             checksum: [0; CHECKSUM_LEN as usize],
         };
-        let actual = packet.actual_checksum();
-        write!(&mut packet.checksum[..], "{:02X}", actual).unwrap();
+        let actual = packet.actual_checksum_with::<C>();
+        write!(&mut packet.checksum[..], "{:02x}", actual).unwrap();
         Self::assume_checked(packet)
     }
+
+    /// Transforms the data of the packet, generating a new checksum for
+    /// the result. The kind of the packet is kept.
+    ///
+    /// ```rust
+    /// # use gdb_protocol::packet::{CheckedPacket, Kind};
+    /// let packet = CheckedPacket::from_data(Kind::Notification, b"Stop:T05".to_vec());
+    /// let packet = packet.map_data(|mut data| {
+    ///     data.extend_from_slice(b"thread:1;");
+    ///     data
+    /// });
+    /// assert!(packet.is_valid());
+    /// assert_eq!(
+    ///     packet,
+    ///     CheckedPacket::from_data(Kind::Notification, b"Stop:T05thread:1;".to_vec())
+    /// );
+    /// ```
+    pub fn map_data<F>(self, f: F) -> Self
+    where
+        F: FnOnce(Vec<u8>) -> Vec<u8>,
+    {
+        let UncheckedPacket { kind, data, .. } = self.unchecked;
+        Self::from_data(kind, f(data))
+    }
+
+    /// Succeeds if this is the `OK` reply, and fails with
+    /// `Error::Malformed` otherwise. Use `as_error` to tell whether the
+    /// command failed.
+    ///
+    /// ```rust
+    /// # use gdb_protocol::{commands, packet::CheckedPacket};
+    /// assert!(commands::ok_reply().expect_ok().is_ok());
+    /// assert!(commands::error_reply(5).expect_ok().is_err());
+    /// assert!(CheckedPacket::empty().expect_ok().is_err());
+    /// ```
+    pub fn expect_ok(&self) -> Result<(), Error> {
+        if self.data == b"OK" {
+            Ok(())
+        } else {
+            Err(Error::Malformed(self.data.clone()))
+        }
+    }
+    /// Return the error number if this is an `Enn` reply, see
+    /// `commands::parse_error_reply`.
+    ///
+    /// ```rust
+    /// # use gdb_protocol::{commands, packet::CheckedPacket};
+    /// assert_eq!(commands::error_reply(5).as_error(), Some(5));
+    /// assert_eq!(commands::ok_reply().as_error(), None);
+    /// assert_eq!(CheckedPacket::empty().as_error(), None);
+    /// ```
+    pub fn as_error(&self) -> Option<u8> {
+        commands::parse_error_reply(self)
+    }
+    /// Returns true if this is an empty reply, which is how a stub
+    /// tells a packet is not supported. See `commands::Unsupported`.
+    ///
+    /// ```rust
+    /// # use gdb_protocol::{commands, parser::parse_all};
+    /// let packet = parse_all(b"$#00").unwrap().remove(0).check().unwrap();
+    /// assert!(packet.is_unsupported());
+    /// assert!(!commands::ok_reply().is_unsupported());
+    /// assert!(!commands::error_reply(5).is_unsupported());
+    /// ```
+    pub fn is_unsupported(&self) -> bool {
+        self.data.is_empty()
+    }
+    /// Returns true if the packet has no data. Its checksum is then
+    /// `00`, as the sum of no bytes is 0.
+    ///
+    /// ```rust
+    /// # use gdb_protocol::packet::{CheckedPacket, Kind};
+    /// assert!(CheckedPacket::empty().is_empty());
+    /// assert_eq!(CheckedPacket::empty().checksum, *b"00");
+    /// assert!(CheckedPacket::notification(Vec::new()).is_empty());
+    /// assert!(!CheckedPacket::from_data(Kind::Packet, b"OK".to_vec()).is_empty());
+    /// ```
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+/// Compares the kind, data and checksum of the packets, regardless of
+/// whether they have been checked.
+///
+/// ```rust
+/// # use gdb_protocol::packet::{CheckedPacket, Kind, UncheckedPacket};
+/// let checked = CheckedPacket::from_data(Kind::Packet, b"Hello, World!".to_vec());
+/// let mut unchecked = UncheckedPacket {
+///     kind: Kind::Packet,
+///     data: b"Hello, World!".to_vec(),
+///     checksum: *b"69",
+/// };
+/// assert_eq!(checked, unchecked);
+/// assert_eq!(unchecked, checked);
+///
+/// unchecked.checksum = *b"00";
+/// assert_ne!(checked, unchecked);
+/// assert_ne!(unchecked, checked);
+/// ```
+impl PartialEq<UncheckedPacket> for CheckedPacket {
+    fn eq(&self, other: &UncheckedPacket) -> bool {
+        self.unchecked == *other
+    }
+}
+impl PartialEq<CheckedPacket> for UncheckedPacket {
+    fn eq(&self, other: &CheckedPacket) -> bool {
+        *self == other.unchecked
+    }
 }
 // No DerefMut, because then the checksum/data could be modified
 impl Deref for CheckedPacket {
@@ -1,10 +1,15 @@
 use super::{
-    packet::{Kind, UncheckedPacket},
+    packet::{CheckedPacket, Checksum, Gdb8BitSum, Kind, UncheckedPacket},
     Error,
 };
 
-use std::{cmp, iter, mem};
+use std::{
+    cmp,
+    io::{self, Read},
+    iter, mem,
+};
 
+#[derive(Clone)]
 enum State {
     Type,         // % or $
     Data,         // packet-data#
@@ -15,14 +20,82 @@ enum State {
 
 pub const CHECKSUM_LEN: u8 = 2;
 
-pub struct Parser {
+/// What the parser is currently waiting for, for diagnostics.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "unstable", non_exhaustive)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ParserPhase {
+    /// The start of a packet
+    Type,
+    /// Packet data, up to the `#`
+    Data,
+    /// The byte after a `}`
+    Escape,
+    /// The repeat count after a `*`
+    Repeat,
+    /// The checksum digits
+    Checksum,
+}
+
+/// The location of a packet in the stream of bytes fed to a `Parser`,
+/// from its `$`/`%` up to and excluding the end of its checksum.
+/// Offsets count all bytes consumed by the parser since it was
+/// created, see `Parser::bytes_consumed`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PacketSpan {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// The configuration of a `Parser` and its progress through a packet,
+/// see `Parser::snapshot`. This is serializable with the `serde`
+/// feature.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ParserSnapshot {
+    phase: ParserPhase,
+    kind: Kind,
+    data: Vec<u8>,
+    /// The checksum digits read so far
+    checksum: Vec<u8>,
+    strict: bool,
+    max_len: Option<usize>,
+    implicit_start: bool,
+    implicit: bool,
+    after_ack: bool,
+    after_checksum: bool,
+}
+
+/// Parses packets out of a stream, verifying their checksums with `C`,
+/// the one specified by GDB unless another is picked with
+/// `with_checksum`.
+#[derive(Clone)]
+pub struct Parser<C = Gdb8BitSum> {
     state: State,
     kind: Kind,
     data: Vec<u8>,
     checksum: [u8; CHECKSUM_LEN as usize],
+    running: C,
+    consumed: usize,
+    start: usize,
+    strict: bool,
+    max_len: Option<usize>,
+    implicit_start: bool,
+    /// Whether the current packet started without a `$`
+    implicit: bool,
+    after_ack: bool,
+    after_checksum: bool,
+    readahead: Vec<u8>,
 }
 impl Default for Parser {
     fn default() -> Self {
+        Self::with_checksum()
+    }
+}
+impl<C: Checksum> Parser<C> {
+    /// Creates a parser verifying checksums with `C` rather than
+    /// `Gdb8BitSum`, such as for a variant of the protocol.
+    pub fn with_checksum() -> Self {
         Self {
             state: State::Type,
 
@@ -30,10 +103,69 @@ impl Default for Parser {
             kind: Kind::Notification,
             data: Vec::new(),
             checksum: [0; CHECKSUM_LEN as usize],
+            running: C::default(),
+            consumed: 0,
+            start: 0,
+            strict: false,
+            max_len: None,
+            implicit_start: false,
+            implicit: false,
+            after_ack: false,
+            after_checksum: false,
+            readahead: Vec::new(),
         }
     }
-}
-impl Parser {
+    /// By default, any bytes in between packets are skipped as
+    /// garbage. In strict mode, only acknowledgments and whitespace
+    /// are allowed there, and anything else causes an
+    /// `Error::UnexpectedByte`. The unexpected byte is consumed, so
+    /// parsing can go on past it. This is useful in tests, to catch
+    /// framing bugs.
+    ///
+    /// Interrupts (a bare `0x03`) are allowed as well.
+    ///
+    /// Strict mode also requires the checksum to consist of
+    /// hexadecimal digits, causing an `Error::InvalidChecksumDigit` as
+    /// soon as any other byte is read. The packet is then discarded.
+    /// Lenient parsing leaves this to `UncheckedPacket::check`.
+    ///
+    /// Strict mode also rejects run-length counts the specification
+    /// forbids (`#`, `$` and anything above `~`), and run-lengths at
+    /// the start of a packet, which have nothing to repeat, with
+    /// `Error::Malformed`, discarding the packet. Lenient parsing drops
+    /// the latter.
+    ///
+    /// Finally, strict mode requires the byte right after a checksum
+    /// to be one allowed in between packets, or the start of the next
+    /// packet, catching peers which send checksums of more than two
+    /// digits. Anything else causes an `Error::TrailingChecksumByte`
+    /// on the next `feed`, since the packet itself has already been
+    /// returned. The byte is consumed, like an unexpected one.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+    /// Limits the length of the decoded data of a packet. Once a packet
+    /// would grow past `max` bytes, it is discarded and
+    /// `Error::PacketTooLarge` is returned. The limit is checked before
+    /// the data is extended, so run-length sequences can't make the
+    /// buffer grow past it either.
+    pub fn set_max_len(&mut self, max: usize) {
+        self.max_len = Some(max);
+    }
+    /// Some buggy stubs omit the `$` of a reply, sending `+OK#9a`
+    /// for example. When enabled, anything other than a `$`, `%`,
+    /// another acknowledgment, an interrupt or whitespace directly
+    /// after an acknowledgment is taken to be the start of the data of
+    /// a packet, as if it was preceded by `$`. If a `$` or `%` follows
+    /// before the `#`, the packet starts over from there. This is
+    /// disabled by default.
+    ///
+    /// Acknowledgments which `io::GdbServer::dispatch` reads itself,
+    /// rather than feeding them to the parser, count as well.
+    pub fn set_implicit_start(&mut self, implicit: bool) {
+        self.implicit_start = implicit;
+    }
+
     /// Parse as much of `input` as possible into a packet. Returns
     /// the number of bytes read (the rest will need to be re-fed),
     /// and maybe a packet which will need handling.
@@ -115,10 +247,317 @@ impl Parser {
     /// # Ok::<(), Error>(())
     /// ```
     pub fn feed(&mut self, input: &[u8]) -> Result<(usize, Option<UncheckedPacket>), Error> {
+        let (read, packet) = self.feed_summed(input)?;
+        Ok((read, packet.map(|(packet, _)| packet)))
+    }
+    /// Reads from `reader` until a packet is complete, and returns it.
+    /// Any input read past the end of the packet is kept in an internal
+    /// buffer for the next call. Returns `None` on EOF, or
+    /// `Error::UnexpectedEof` if the input ended in the middle of a
+    /// packet, like `io::read_packet`. The buffered input is discarded
+    /// if parsing fails.
+    pub fn feed_reader<R>(&mut self, reader: &mut R) -> Result<Option<UncheckedPacket>, Error>
+    where
+        R: Read,
+    {
+        let mut chunk = [0; 1024];
+        loop {
+            if !self.readahead.is_empty() {
+                let input = mem::take(&mut self.readahead);
+                let (read, packet) = self.feed(&input)?;
+                self.readahead = input;
+                self.readahead.drain(..read);
+                if packet.is_some() {
+                    return Ok(packet);
+                }
+            }
+
+            let read = match reader.read(&mut chunk) {
+                Ok(read) => read,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            };
+            if read == 0 {
+                self.finish()?;
+                return Ok(None);
+            }
+            self.readahead.extend_from_slice(&chunk[..read]);
+        }
+    }
+    /// Like `feed`, but also verifies the checksum of the resulting
+    /// packet. The actual checksum is accumulated while the data is
+    /// parsed, so this is only a comparison and does not re-scan the
+    /// data. A packet with an invalid checksum is returned as `Err`.
+    ///
+    /// ```rust
+    /// # use gdb_protocol::{Error, packet::{CheckedPacket, Kind}, parser::Parser};
+    /// # let mut parser = Parser::default();
+    /// let (_, packet) = parser.feed_checked(b"$hello#14")?;
+    /// assert_eq!(packet, Some(Ok(CheckedPacket::from_data(Kind::Packet, b"hello".to_vec()))));
+    ///
+    /// let (_, packet) = parser.feed_checked(b"$hello#00")?;
+    /// assert!(packet.unwrap().is_err());
+    /// # Ok::<(), Error>(())
+    /// ```
+    #[allow(clippy::type_complexity)]
+    pub fn feed_checked(
+        &mut self,
+        input: &[u8],
+    ) -> Result<(usize, Option<Result<CheckedPacket, UncheckedPacket>>), Error> {
+        let (read, packet) = self.feed_summed(input)?;
+        Ok((
+            read,
+            packet.map(|(packet, actual)| {
+                if packet.expected_checksum().ok() == Some(actual) {
+                    Ok(CheckedPacket::assume_checked(packet))
+                } else {
+                    Err(packet)
+                }
+            }),
+        ))
+    }
+    /// Call this at the end of the input, to make sure no packet is
+    /// left partially parsed. A truncated packet causes an
+    /// `Error::UnexpectedEof`.
+    ///
+    /// ```rust
+    /// # use gdb_protocol::{Error, parser::Parser};
+    /// let mut parser = Parser::default();
+    /// parser.feed(b"$hello#14").unwrap();
+    /// assert!(parser.finish().is_ok());
+    ///
+    /// parser.feed(b"$hello#1").unwrap();
+    /// assert!(matches!(parser.finish(), Err(Error::UnexpectedEof)));
+    /// ```
+    pub fn finish(&self) -> Result<(), Error> {
+        if self.is_idle() {
+            Ok(())
+        } else {
+            Err(Error::UnexpectedEof)
+        }
+    }
+    /// Discards any partially parsed packet, so the parser starts
+    /// looking for the next `$` or `%`. The configuration, and the
+    /// count of consumed bytes, are kept.
+    pub fn reset(&mut self) {
+        self.state = State::Type;
+        self.implicit = false;
+        self.after_ack = false;
+        self.after_checksum = false;
+        self.data.clear();
+        self.running = C::default();
+    }
+    /// Return the current phase of the parser, for example to explain
+    /// why a read is blocking.
+    ///
+    /// ```rust
+    /// # use gdb_protocol::parser::{Parser, ParserPhase};
+    /// let mut parser = Parser::default();
+    /// assert_eq!(parser.phase(), ParserPhase::Type);
+    /// parser.feed(b"$ab").unwrap();
+    /// assert_eq!(parser.phase(), ParserPhase::Data);
+    /// assert_eq!(parser.pending_data_len(), 2);
+    /// ```
+    pub fn phase(&self) -> ParserPhase {
+        match self.state {
+            State::Type => ParserPhase::Type,
+            State::Data => ParserPhase::Data,
+            State::Escape => ParserPhase::Escape,
+            State::Repeat => ParserPhase::Repeat,
+            State::Checksum(_) => ParserPhase::Checksum,
+        }
+    }
+    /// Capture the configuration and the state of the parser, to be
+    /// restored later, possibly by another process.
+    ///
+    /// ```rust
+    /// # use gdb_protocol::{Error, parser::Parser};
+    /// let mut parser = Parser::default();
+    /// parser.feed(b"$hel")?;
+    /// let snapshot = parser.snapshot();
+    ///
+    /// let mut parser = Parser::default();
+    /// parser.restore(snapshot);
+    /// let (_, packet) = parser.feed(b"lo#14")?;
+    /// assert_eq!(packet.unwrap().data, b"hello");
+    /// # Ok::<(), Error>(())
+    /// ```
+    pub fn snapshot(&self) -> ParserSnapshot {
+        let checksum = match self.state {
+            State::Checksum(i) => self.checksum[..i as usize].to_vec(),
+            _ => Vec::new(),
+        };
+        ParserSnapshot {
+            phase: self.phase(),
+            kind: self.kind,
+            data: self.data.clone(),
+            checksum,
+            strict: self.strict,
+            max_len: self.max_len,
+            implicit_start: self.implicit_start,
+            implicit: self.implicit,
+            after_ack: self.after_ack,
+            after_checksum: self.after_checksum,
+        }
+    }
+    /// Continue from a `snapshot`. The count of consumed bytes and any
+    /// input buffered by `feed_reader` are not part of the snapshot,
+    /// and are kept.
+    pub fn restore(&mut self, snapshot: ParserSnapshot) {
+        let digits = cmp::min(snapshot.checksum.len(), CHECKSUM_LEN as usize - 1);
+        self.state = match snapshot.phase {
+            ParserPhase::Type => State::Type,
+            ParserPhase::Data => State::Data,
+            ParserPhase::Escape => State::Escape,
+            ParserPhase::Repeat => State::Repeat,
+            ParserPhase::Checksum => State::Checksum(digits as u8),
+        };
+        self.checksum[..digits].copy_from_slice(&snapshot.checksum[..digits]);
+        self.kind = snapshot.kind;
+        self.running = C::default();
+        self.running.update(&snapshot.data);
+        self.data = snapshot.data;
+        self.strict = snapshot.strict;
+        self.max_len = snapshot.max_len;
+        self.implicit_start = snapshot.implicit_start;
+        self.implicit = snapshot.implicit;
+        self.after_ack = snapshot.after_ack;
+        self.after_checksum = snapshot.after_checksum;
+    }
+    /// Return the length of the data of the partially parsed packet.
+    pub fn pending_data_len(&self) -> usize {
+        self.data.len()
+    }
+    /// Fails if adding `added` bytes of data would exceed the maximum
+    /// length, discarding the packet.
+    fn check_len(&mut self, added: usize) -> Result<(), Error> {
+        let len = self.data.len() + added;
+        match self.max_len {
+            Some(max) if len > max => {
+                let kind = self.kind;
+                self.reset();
+                Err(Error::PacketTooLarge { kind, len, max })
+            }
+            _ => Ok(()),
+        }
+    }
+    /// Returns true if the parser is in between packets.
+    pub(crate) fn is_idle(&self) -> bool {
+        matches!(self.state, State::Type)
+    }
+    /// Like `feed`, but also returns where the packet is located in the
+    /// stream. The span is relative to the first byte ever fed to this
+    /// parser, so a packet split across multiple calls is still
+    /// located correctly.
+    pub fn feed_spans(
+        &mut self,
+        input: &[u8],
+    ) -> Result<(usize, Option<(UncheckedPacket, PacketSpan)>), Error> {
+        let (read, packet) = self.feed(input)?;
+        let span = PacketSpan {
+            start: self.start,
+            end: self.consumed,
+        };
+        Ok((read, packet.map(|packet| (packet, span))))
+    }
+    /// Like `feed`, but also returns the raw bytes of the packet as
+    /// received, from the `$` or `%` up to and including the checksum.
+    /// These are only available if the whole packet was in `input`,
+    /// and are `None` if it was spanned across multiple calls. This
+    /// lets a relay forward a packet exactly as it was encoded.
+    ///
+    /// ```rust
+    /// # use gdb_protocol::{Error, parser::Parser};
+    /// let input = b"+$a}]b*!#00$g#67";
+    /// let (read, packet) = Parser::default().feed_raw(input)?;
+    /// let (packet, raw) = packet.unwrap();
+    /// assert_eq!(packet.data, b"a}bbbbb");
+    /// assert_eq!(raw.unwrap(), &input[1..read]);
+    ///
+    /// let mut parser = Parser::default();
+    /// parser.feed_raw(b"$g#6")?;
+    /// let (_, packet) = parser.feed_raw(b"7")?;
+    /// assert_eq!(packet.unwrap().1, None);
+    /// # Ok::<(), Error>(())
+    /// ```
+    #[allow(clippy::type_complexity)]
+    pub fn feed_raw(
+        &mut self,
+        input: &[u8],
+    ) -> Result<(usize, Option<(UncheckedPacket, Option<Vec<u8>>)>), Error> {
+        let base = self.consumed;
+        let (read, packet) = self.feed(input)?;
+        Ok((
+            read,
+            packet.map(|packet| {
+                // Only copied once the packet is complete
+                let raw = if self.start >= base {
+                    Some(input[self.start - base..self.consumed - base].to_vec())
+                } else {
+                    None
+                };
+                (packet, raw)
+            }),
+        ))
+    }
+    /// Returns the length of the start of `input` which completes
+    /// packets, including any bytes in between or after them, leaving
+    /// out a packet cut off at the end. A proxy can forward that many
+    /// bytes verbatim and keep the rest until more input arrives. The
+    /// parser itself is left untouched, and input it would fail on
+    /// ends the prefix.
+    ///
+    /// ```rust
+    /// # use gdb_protocol::parser::Parser;
+    /// let parser = Parser::default();
+    /// assert_eq!(parser.complete_prefix_len(b"+$g#67+$m0,4"), 7);
+    /// ```
+    pub fn complete_prefix_len(&self, input: &[u8]) -> usize {
+        let base = self.consumed;
+        let mut parser = self.clone();
+        let mut read = 0;
+        let mut complete = 0;
+        while read < input.len() {
+            match parser.feed(&input[read..]) {
+                Ok((0, _)) | Err(_) => break,
+                Ok((partial, _)) => read += partial,
+            }
+            complete = if parser.is_idle() {
+                read
+            } else {
+                // Whatever precedes a packet is complete already
+                parser.start.saturating_sub(base)
+            };
+        }
+        complete
+    }
+    /// Return the total number of bytes consumed since the parser was
+    /// created.
+    pub fn bytes_consumed(&self) -> usize {
+        self.consumed
+    }
+    /// Counts bytes taken from the stream without being fed to the
+    /// parser, so the offsets stay those of the whole stream.
+    pub(crate) fn skip(&mut self, len: usize) {
+        self.consumed += len;
+    }
+    /// Like `skip`, for an acknowledgment, which may be followed by a
+    /// packet missing its `$`, see `set_implicit_start`.
+    pub(crate) fn skip_ack(&mut self) {
+        self.skip(1);
+        if self.implicit_start && self.is_idle() {
+            self.after_ack = true;
+        }
+    }
+    fn feed_summed(
+        &mut self,
+        input: &[u8],
+    ) -> Result<(usize, Option<(UncheckedPacket, u8)>), Error> {
         let mut read = 0;
         loop {
             let (partial, packet) = self.feed_one(&input[read..])?;
             read += partial;
+            self.consumed += partial;
             debug_assert!(read <= input.len());
 
             if read == input.len() || packet.is_some() {
@@ -126,7 +565,7 @@ impl Parser {
             }
         }
     }
-    fn feed_one(&mut self, input: &[u8]) -> Result<(usize, Option<UncheckedPacket>), Error> {
+    fn feed_one(&mut self, input: &[u8]) -> Result<(usize, Option<(UncheckedPacket, u8)>), Error> {
         let first = match input.first() {
             Some(b) => *b,
             None => return Ok((0, None)),
@@ -134,7 +573,57 @@ impl Parser {
 
         match self.state {
             State::Type => {
+                if self.after_checksum {
+                    let boundary = match first {
+                        b'$' | b'%' | b'+' | b'-' | 0x03 => true,
+                        _ => first.is_ascii_whitespace(),
+                    };
+                    self.after_checksum = false;
+                    if self.strict && !boundary {
+                        self.consumed += 1;
+                        return Err(Error::TrailingChecksumByte(first));
+                    }
+                }
+
                 let start = memchr::memchr2(b'%', b'$', input);
+                let mut implicit = None;
+                if self.implicit_start {
+                    for (pos, &b) in input[..start.unwrap_or(input.len())].iter().enumerate() {
+                        match b {
+                            b'+' | b'-' => self.after_ack = true,
+                            0x03 => self.after_ack = false,
+                            // Whitespace is allowed in between packets
+                            _ if b.is_ascii_whitespace() => (),
+                            _ if self.after_ack => {
+                                implicit = Some(pos);
+                                break;
+                            }
+                            _ => (),
+                        }
+                    }
+                }
+
+                if self.strict {
+                    let end = implicit.or(start).unwrap_or(input.len());
+                    let garbage = input[..end].iter().position(|&b| {
+                        b != b'+' && b != b'-' && b != 0x03 && !b.is_ascii_whitespace()
+                    });
+                    if let Some(pos) = garbage {
+                        // Skip it, or the next feed would fail on it again
+                        self.consumed += pos + 1;
+                        return Err(Error::UnexpectedByte(input[pos]));
+                    }
+                }
+
+                if let Some(pos) = implicit {
+                    // The data starts right away, so nothing is skipped
+                    self.after_ack = false;
+                    self.implicit = true;
+                    self.kind = Kind::Packet;
+                    self.start = self.consumed + pos;
+                    self.state = State::Data;
+                    return Ok((pos, None));
+                }
 
                 match start.map(|pos| input[pos]) {
                     Some(b'%') => self.kind = Kind::Notification,
@@ -143,15 +632,39 @@ impl Parser {
                     None => (),
                 }
 
-                if start.is_some() {
+                if let Some(start) = start {
+                    self.after_ack = false;
+                    self.implicit = false;
+                    self.start = self.consumed + start;
                     self.state = State::Data;
                 }
 
                 Ok((start.map(|n| n + 1).unwrap_or_else(|| input.len()), None))
             }
             State::Data => {
+                // Runs of data are handled in one go, so a large packet
+                // costs a scan, a copy and a sum of the data, as
+                // measured by benches/parser.rs
                 let end = memchr::memchr3(b'#', b'}', b'*', input);
 
+                if self.implicit {
+                    // The packet was only assumed to start, so a real
+                    // start restarts it
+                    let data = &input[..end.unwrap_or(input.len())];
+                    if let Some(pos) = memchr::memchr2(b'$', b'%', data) {
+                        self.implicit = false;
+                        self.kind = if data[pos] == b'$' {
+                            Kind::Packet
+                        } else {
+                            Kind::Notification
+                        };
+                        self.start = self.consumed + pos;
+                        self.data.clear();
+                        self.running = C::default();
+                        return Ok((pos + 1, None));
+                    }
+                }
+
                 match end.map(|pos| input[pos]) {
                     Some(b'#') => self.state = State::Checksum(0),
                     Some(b'}') => self.state = State::Escape,
@@ -160,29 +673,51 @@ impl Parser {
                     None => (),
                 }
 
-                self.data
-                    .extend_from_slice(&input[..end.unwrap_or_else(|| input.len())]);
+                let data = &input[..end.unwrap_or(input.len())];
+                self.check_len(data.len())?;
+                self.data.extend_from_slice(data);
+                self.running.update(data);
                 Ok((end.map(|n| n + 1).unwrap_or_else(|| input.len()), None))
             }
             State::Escape => {
+                self.check_len(1)?;
                 self.data.push(first ^ 0x20);
+                self.running.update(&[first ^ 0x20]);
                 self.state = State::Data;
                 Ok((1, None))
             }
             State::Repeat => {
-                let c = *self
-                    .data
-                    .last()
-                    .expect("State::Repeat must only be used once data has been inserted");
-                let count = first.saturating_sub(29);
-                self.data.extend(iter::repeat(c).take(count.into()));
+                // A packet starting with a repeat has nothing to repeat,
+                // so the run-length is dropped unless strict. The
+                // checksum covers the decoded data, so it can't tell.
+                let forbidden = first == b'#' || first == b'$' || first > b'~';
+                if self.strict && (forbidden || self.data.is_empty()) {
+                    self.reset();
+                    return Err(Error::Malformed(vec![b'*', first]));
+                }
+                if let Some(&c) = self.data.last() {
+                    let count = first.saturating_sub(29);
+                    self.check_len(count.into())?;
+                    let start = self.data.len();
+                    #[allow(clippy::manual_repeat_n)]
+                    self.data.extend(iter::repeat(c).take(count.into()));
+                    self.running.update(&self.data[start..]);
+                }
                 self.state = State::Data;
                 Ok((1, None))
             }
             State::Checksum(mut i) => {
                 let read = cmp::min((CHECKSUM_LEN - i) as usize, input.len());
 
-                self.checksum[i as usize..].copy_from_slice(&input[..read]);
+                if self.strict {
+                    let invalid = input[..read].iter().find(|b| !b.is_ascii_hexdigit());
+                    if let Some(&b) = invalid {
+                        self.reset();
+                        return Err(Error::InvalidChecksumDigit(b));
+                    }
+                }
+
+                self.checksum[i as usize..i as usize + read].copy_from_slice(&input[..read]);
                 i += read as u8; // read <= CHECKSUM_LEN
 
                 if i < CHECKSUM_LEN {
@@ -190,17 +725,430 @@ impl Parser {
                     Ok((read, None))
                 } else {
                     self.state = State::Type;
+                    self.after_checksum = true;
 
                     Ok((
                         read,
-                        Some(UncheckedPacket {
-                            kind: self.kind,
-                            data: mem::replace(&mut self.data, Vec::new()),
-                            checksum: self.checksum,
-                        }),
+                        Some((
+                            UncheckedPacket {
+                                kind: self.kind,
+                                #[allow(clippy::mem_replace_with_default)]
+                                data: mem::replace(&mut self.data, Vec::new()),
+                                checksum: self.checksum,
+                            },
+                            mem::take(&mut self.running).finalize(),
+                        )),
                     ))
                 }
             }
         }
     }
 }
+
+/// Parse all packets of a complete input, such as a capture of a
+/// session. Garbage in between packets is skipped as usual, but input
+/// ending in the middle of a packet causes an `Error::UnexpectedEof`.
+pub fn parse_all(mut input: &[u8]) -> Result<Vec<UncheckedPacket>, Error> {
+    let mut parser = Parser::default();
+    let mut packets = Vec::new();
+    while !input.is_empty() {
+        let (read, packet) = parser.feed(input)?;
+        input = &input[read..];
+        packets.extend(packet);
+    }
+    parser.finish()?;
+    Ok(packets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CORPUS: &[&[u8]] = &[
+        b"$hello#14",
+        b"$in:valid}]}}Hello* }]*!CHECKS#UM",
+        &[b'%', 1, 2, 99, 255, 128, 0, 200, b'#', 0, 0],
+        b"$#00",
+        b"$these must be escaped: }\x03 }\x04 }] }\x0a#00",
+    ];
+
+    #[test]
+    fn it_sums_incrementally() {
+        for input in CORPUS {
+            let mut parser = Parser::default();
+            let (_, packet) = parser.feed_summed(input).unwrap();
+            let (packet, actual) = packet.unwrap();
+            assert_eq!(actual, packet.actual_checksum());
+
+            let mut parser = Parser::default();
+            let mut result = None;
+            for chunk in input.chunks(1) {
+                if let (_, Some(packet)) = parser.feed_summed(chunk).unwrap() {
+                    result = Some(packet);
+                }
+            }
+            let (packet, actual) = result.unwrap();
+            assert_eq!(actual, packet.actual_checksum());
+        }
+    }
+    #[test]
+    fn it_locates_split_packets() {
+        let mut parser = Parser::default();
+        assert_eq!(parser.feed_spans(b"xx$hel").unwrap(), (6, None));
+        assert_eq!(parser.bytes_consumed(), 6);
+
+        let (read, packet) = parser.feed_spans(b"lo#14yy").unwrap();
+        let (packet, span) = packet.unwrap();
+        assert_eq!(read, 5);
+        assert_eq!(packet.data, b"hello");
+        assert_eq!(span, PacketSpan { start: 2, end: 11 });
+        assert_eq!(parser.bytes_consumed(), 11);
+
+        assert_eq!(
+            parser.feed_spans(b"yy%ok#00").unwrap().1.unwrap().1,
+            PacketSpan { start: 13, end: 19 }
+        );
+    }
+    #[test]
+    fn it_rejects_garbage_in_strict_mode() {
+        let input = b"+ garbage $ok#da";
+
+        let mut parser = Parser::default();
+        let (read, packet) = parser.feed(input).unwrap();
+        assert_eq!(read, input.len());
+        assert_eq!(packet.unwrap().data, b"ok");
+
+        let mut parser = Parser::default();
+        parser.set_strict(true);
+        match parser.feed(input) {
+            Err(Error::UnexpectedByte(b'g')) => (),
+            result => panic!("Expected error UnexpectedByte, got {:?}", result),
+        }
+        assert_eq!(parser.bytes_consumed(), 3);
+        match parser.feed(&input[3..]) {
+            Err(Error::UnexpectedByte(b'a')) => (),
+            result => panic!("Expected error UnexpectedByte, got {:?}", result),
+        }
+
+        let mut parser = Parser::default();
+        parser.set_strict(true);
+        let (_, packet) = parser.feed(b"+-\r\n $ok#da").unwrap();
+        assert_eq!(packet.unwrap().data, b"ok");
+    }
+    #[test]
+    fn it_rejects_non_hex_checksums_in_strict_mode() {
+        let mut parser = Parser::default();
+        let (_, packet) = parser.feed(b"$ok#ZZ").unwrap();
+        assert_eq!(packet.unwrap().checksum, *b"ZZ");
+
+        let mut parser = Parser::default();
+        parser.set_strict(true);
+        match parser.feed(b"$ok#ZZ") {
+            Err(Error::InvalidChecksumDigit(b'Z')) => (),
+            result => panic!("Expected error InvalidChecksumDigit, got {:?}", result),
+        }
+        let (_, packet) = parser.feed(b"$ok#da").unwrap();
+        assert_eq!(packet.unwrap().data, b"ok");
+    }
+    #[test]
+    fn it_rejects_trailing_checksum_bytes_in_strict_mode() {
+        let input = b"$ok#9a5";
+
+        let mut parser = Parser::default();
+        let (read, _) = parser.feed(input).unwrap();
+        assert_eq!(parser.feed(&input[read..]).unwrap(), (1, None));
+
+        let mut parser = Parser::default();
+        parser.set_strict(true);
+        let (read, packet) = parser.feed(input).unwrap();
+        assert_eq!(read, input.len() - 1);
+        assert_eq!(packet.unwrap().checksum, *b"9a");
+        match parser.feed(&input[read..]) {
+            Err(Error::TrailingChecksumByte(b'5')) => (),
+            result => panic!("Expected error TrailingChecksumByte, got {:?}", result),
+        }
+        assert_eq!(parser.bytes_consumed(), input.len());
+        let (_, packet) = parser.feed(b"$ok#da").unwrap();
+        assert_eq!(packet.unwrap().data, b"ok");
+
+        // The checksum may be split from the byte after it
+        parser.reset();
+        parser.feed(b"$ok#d").unwrap();
+        let (_, packet) = parser.feed(b"a").unwrap();
+        assert_eq!(packet.unwrap().data, b"ok");
+        match parser.feed(b"0") {
+            Err(Error::TrailingChecksumByte(b'0')) => (),
+            result => panic!("Expected error TrailingChecksumByte, got {:?}", result),
+        }
+
+        parser.reset();
+        let (_, packet) = parser.feed(b"$ok#da+$ok#da").unwrap();
+        assert_eq!(packet.unwrap().data, b"ok");
+        let (_, packet) = parser.feed(b"+$ok#da").unwrap();
+        assert_eq!(packet.unwrap().data, b"ok");
+    }
+    #[test]
+    fn it_reports_its_phase() {
+        let mut parser = Parser::default();
+        let phases = [
+            (&b"$a"[..], ParserPhase::Data),
+            (b"}", ParserPhase::Escape),
+            (b"]", ParserPhase::Data),
+            (b"*", ParserPhase::Repeat),
+            (b" ", ParserPhase::Data),
+            (b"#", ParserPhase::Checksum),
+            (b"0", ParserPhase::Checksum),
+            (b"0", ParserPhase::Type),
+        ];
+        for &(input, phase) in &phases {
+            parser.feed(input).unwrap();
+            assert_eq!(parser.phase(), phase, "after {:?}", input);
+        }
+        assert_eq!(parser.pending_data_len(), 0);
+    }
+    #[test]
+    fn it_reads_checksum_digits_singly() {
+        let mut parser = Parser::default();
+        assert_eq!(parser.feed(b"$hello#").unwrap(), (7, None));
+        assert_eq!(parser.feed(b"1").unwrap(), (1, None));
+        assert!(parser.finish().is_err());
+        let (read, packet) = parser.feed(b"4").unwrap();
+        assert_eq!(read, 1);
+        assert_eq!(packet.unwrap().checksum, *b"14");
+        assert!(parser.finish().is_ok());
+    }
+    #[test]
+    fn it_expands_repeats_split_across_feeds() {
+        let input = b"$ab*\"cd#74";
+        let expected = Parser::default().feed_checked(input).unwrap();
+        assert_eq!(
+            expected,
+            (
+                input.len(),
+                Some(Ok(CheckedPacket::from_data(
+                    Kind::Packet,
+                    b"abbbbbbcd".to_vec()
+                )))
+            )
+        );
+
+        // Split between the repeated byte, the '*', and the count
+        let star = memchr::memchr(b'*', input).unwrap();
+        let splits = [
+            (star - 1, star),
+            (star, star + 1),
+            (star - 1, star + 1),
+            (star + 1, star + 2),
+        ];
+        for &(first, second) in &splits {
+            let mut parser = Parser::default();
+            let (a, packet_a) = parser.feed_checked(&input[..first]).unwrap();
+            let (b, packet_b) = parser.feed_checked(&input[first..second]).unwrap();
+            let (c, packet_c) = parser.feed_checked(&input[second..]).unwrap();
+            assert_eq!(a + b + c, input.len(), "split at {}, {}", first, second);
+            assert!(packet_a.is_none() && packet_b.is_none());
+            assert_eq!(packet_c, expected.1, "split at {}, {}", first, second);
+        }
+    }
+    #[test]
+    fn it_survives_repeats_without_data() {
+        let (read, packet) = Parser::default().feed_checked(b"$*\"ab#c3").unwrap();
+        assert_eq!(read, 8);
+        assert_eq!(packet.unwrap().unwrap().data, b"ab");
+
+        let mut parser = Parser::default();
+        parser.set_strict(true);
+        match parser.feed(b"$*\"ab#c3") {
+            Err(Error::Malformed(bytes)) => assert_eq!(bytes, b"*\""),
+            result => panic!("Expected error Malformed, got {:?}", result),
+        }
+        assert!(parser.is_idle());
+    }
+    #[test]
+    fn it_limits_the_packet_length() {
+        let mut parser = Parser::default();
+        parser.set_max_len(64);
+        let mut input = b"$a".to_vec();
+        for _ in 0..1000 {
+            input.extend_from_slice(b"*\xff");
+        }
+        input.extend_from_slice(b"#00");
+        match parser.feed(&input) {
+            Err(Error::PacketTooLarge { kind, len, max }) => {
+                assert_eq!((kind, len, max), (Kind::Packet, 227, 64))
+            }
+            result => panic!("Expected error PacketTooLarge, got {:?}", result),
+        }
+        assert!(parser.is_idle());
+        assert!(parser.data.capacity() < 227);
+
+        for input in &[&b"$0123456789#00"[..], b"$012345678}]#00"] {
+            let mut parser = Parser::default();
+            parser.set_max_len(9);
+            assert!(parser.feed(input).is_err(), "{:?}", input);
+        }
+        let mut parser = Parser::default();
+        parser.set_max_len(10);
+        assert!(parser.feed(b"$0123456789#2d").unwrap().1.is_some());
+    }
+    #[test]
+    fn it_rejects_forbidden_repeat_counts_in_strict_mode() {
+        for input in &[&b"$a*#00"[..], b"$a*$#00", b"$a*\x7f#00"] {
+            let mut parser = Parser::default();
+            assert!(parser.feed(input).is_ok());
+
+            let mut parser = Parser::default();
+            parser.set_strict(true);
+            match parser.feed(input) {
+                Err(Error::Malformed(bytes)) => assert_eq!(bytes, &input[2..4]),
+                result => panic!("Expected error Malformed, got {:?}", result),
+            }
+            assert!(parser.is_idle());
+        }
+    }
+    #[test]
+    fn it_parses_all_packets() {
+        let packets = parse_all(b"+$g#67+$OK#9a-%Stop:T05#99 trailing garbage").unwrap();
+        let data: Vec<&[u8]> = packets.iter().map(|p| &p.data[..]).collect();
+        assert_eq!(data, [&b"g"[..], b"OK", b"Stop:T05"]);
+        assert_eq!(packets[2].kind, Kind::Notification);
+
+        assert!(parse_all(b"").unwrap().is_empty());
+        match parse_all(b"$g#67$OK#9") {
+            Err(Error::UnexpectedEof) => (),
+            result => panic!("Expected error UnexpectedEof, got {:?}", result),
+        }
+    }
+    #[test]
+    fn it_feeds_readers() {
+        use std::io::Cursor;
+
+        // Two reads, the first one ending in the middle of a packet
+        let mut reader = Cursor::new(&b"$hel"[..]).chain(Cursor::new(&b"lo#14$g#67$O"[..]));
+        let mut parser = Parser::default();
+        assert_eq!(
+            parser.feed_reader(&mut reader).unwrap().unwrap().data,
+            b"hello"
+        );
+        assert_eq!(parser.feed_reader(&mut reader).unwrap().unwrap().data, b"g");
+        match parser.feed_reader(&mut reader) {
+            Err(Error::UnexpectedEof) => (),
+            result => panic!("Expected error UnexpectedEof, got {:?}", result),
+        }
+
+        let mut parser = Parser::default();
+        let mut reader = Cursor::new(&b"+"[..]);
+        assert_eq!(parser.feed_reader(&mut reader).unwrap(), None);
+    }
+    #[test]
+    fn it_round_trips_the_escape_byte() {
+        // `}` itself is escaped as `}]`, so data which looks like it
+        // was escaped already has to be escaped again, and is only
+        // unescaped once
+        let cases: &[(&[u8], &[u8])] =
+            &[(b"}", b"$}]#7d"), (b"}]", b"$}]]#da"), (b"}}", b"$}]}]#fa")];
+        for &(data, expected) in cases {
+            let packet = CheckedPacket::from_data(Kind::Packet, data.to_vec());
+            let mut encoded = Vec::new();
+            packet.encode(&mut encoded).unwrap();
+            assert_eq!(encoded, expected);
+
+            let (read, parsed) = Parser::default().feed_checked(&encoded).unwrap();
+            assert_eq!(read, encoded.len());
+            assert_eq!(parsed, Some(Ok(packet)));
+        }
+    }
+    #[test]
+    fn it_assumes_missing_packet_starts_on_request() {
+        let ok = UncheckedPacket {
+            kind: Kind::Packet,
+            data: b"OK".to_vec(),
+            checksum: *b"9a",
+        };
+        assert_eq!(Parser::default().feed(b"+OK#9a").unwrap(), (6, None));
+
+        let mut parser = Parser::default();
+        parser.set_implicit_start(true);
+        assert_eq!(parser.feed(b"+OK#9a").unwrap(), (6, Some(ok.clone())));
+        assert_eq!(parser.feed(b"-").unwrap(), (1, None));
+        assert_eq!(parser.feed(b"OK#9a").unwrap(), (5, Some(ok.clone())));
+        assert_eq!(parser.feed(b"+$OK#9a").unwrap(), (7, Some(ok.clone())));
+        // Only directly after an acknowledgment
+        assert_eq!(parser.feed(b"xOK#9a+\x03").unwrap(), (8, None));
+        assert_eq!(parser.feed(b"OK#9a").unwrap(), (5, None));
+        // Whitespace doesn't start a packet, and a real start restarts
+        // an assumed one
+        assert_eq!(parser.feed(b"+\n$OK#9a").unwrap(), (8, Some(ok.clone())));
+        assert_eq!(parser.feed(b"+\nOK#9a").unwrap(), (7, Some(ok.clone())));
+        assert_eq!(parser.feed(b"+g$OK#9a").unwrap(), (8, Some(ok.clone())));
+        assert_eq!(parser.feed(b"+g").unwrap(), (2, None));
+        assert_eq!(parser.feed(b"%O").unwrap(), (2, None));
+        let (_, packet) = parser.feed(b"K#9a").unwrap();
+        assert_eq!(packet.unwrap().kind, Kind::Notification);
+    }
+    #[test]
+    fn it_restores_snapshots() {
+        let input = b"$in:valid}]}}Hello* }]*!CHECKS#UM";
+        for split in 0..input.len() {
+            let mut parser = Parser::default();
+            parser.set_max_len(100);
+            let (read, packet) = parser.feed(&input[..split]).unwrap();
+            assert_eq!((read, packet), (split, None));
+            let snapshot = parser.snapshot();
+
+            let mut restored = Parser::default();
+            restored.restore(snapshot.clone());
+            assert_eq!(restored.snapshot(), snapshot);
+            assert_eq!(restored.phase(), parser.phase());
+            assert_eq!(
+                restored.feed_summed(&input[split..]).unwrap(),
+                Parser::default()
+                    .feed_summed(input)
+                    .map(|(_, packet)| (input.len() - split, packet))
+                    .unwrap()
+            );
+        }
+    }
+    #[test]
+    fn it_parses_empty_packets() {
+        let empty = UncheckedPacket {
+            kind: Kind::Packet,
+            data: vec![],
+            checksum: *b"00",
+        };
+        assert_eq!(empty.actual_checksum(), 0);
+        assert_eq!(
+            Parser::default().feed(b"$#00").unwrap(),
+            (4, Some(empty.clone()))
+        );
+
+        let (_, packet) = Parser::default().feed_checked(b"$#00").unwrap();
+        let packet = packet.unwrap().unwrap();
+        assert!(packet.is_empty());
+        assert_eq!(packet, CheckedPacket::empty());
+        assert_eq!(packet.invalidate_check(), empty);
+    }
+    #[test]
+    fn it_finds_complete_prefixes() {
+        let parser = Parser::default();
+        let input = b"+$g#67+$m0,4#fd";
+        assert_eq!(parser.complete_prefix_len(input), input.len());
+        for cut in 8..input.len() {
+            assert_eq!(parser.complete_prefix_len(&input[..cut]), 7, "{}", cut);
+        }
+        assert_eq!(parser.complete_prefix_len(b"$g#67 garbage %St"), 14);
+        assert_eq!(parser.complete_prefix_len(b""), 0);
+
+        // A packet started earlier completes as well
+        let mut parser = Parser::default();
+        parser.feed(b"$g").unwrap();
+        assert_eq!(parser.complete_prefix_len(b"#67$q"), 3);
+        assert_eq!(parser.complete_prefix_len(b"#6"), 0);
+        assert_eq!(parser.phase(), ParserPhase::Data);
+        assert_eq!(parser.pending_data_len(), 1);
+
+        let mut parser = Parser::default();
+        parser.set_strict(true);
+        assert_eq!(parser.complete_prefix_len(b"$g#67x$g#67"), 5);
+    }
+}
@@ -0,0 +1,53 @@
+//! Strategies generating packets for property tests with `proptest`,
+//! enabled by the `testing` feature.
+//!
+//! The generated data is biased toward edge cases: empty packets,
+//! bytes that must be escaped, bytes with the high bit set, and long
+//! runs of a single byte.
+
+use crate::packet::{CheckedPacket, Kind, UncheckedPacket};
+
+use proptest::{collection::vec, prelude::*, sample::select};
+
+/// Bytes with a special meaning somewhere in the protocol.
+const SPECIAL: &[u8] = &[
+    b'#', b'$', b'}', b'*', b'%', b'+', b'-', 0x03, 0x20, 0x7f, 0x80, 0xff,
+];
+
+pub fn kind() -> impl Strategy<Value = Kind> {
+    prop_oneof![Just(Kind::Packet), Just(Kind::Notification)]
+}
+
+pub fn data() -> impl Strategy<Value = Vec<u8>> {
+    prop_oneof![
+        Just(Vec::new()),
+        vec(Just(b'}'), 1..64),
+        (any::<u8>(), 1..300usize).prop_map(|(b, n)| vec![b; n]),
+        vec(select(SPECIAL), 1..64),
+        vec(any::<u8>(), 0..512),
+    ]
+}
+
+/// Packets with a well-formed checksum, which is usually wrong.
+pub fn unchecked_packet() -> impl Strategy<Value = UncheckedPacket> {
+    let digit = select(&b"0123456789abcdefABCDEF"[..]);
+    (kind(), data(), digit.clone(), digit).prop_map(|(kind, data, high, low)| UncheckedPacket {
+        kind,
+        data,
+        checksum: [high, low],
+    })
+}
+
+/// Packets with a valid checksum.
+pub fn checked_packet() -> impl Strategy<Value = CheckedPacket> {
+    (kind(), data()).prop_map(|(kind, data)| CheckedPacket::from_data(kind, data))
+}
+
+impl Arbitrary for UncheckedPacket {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_args: ()) -> Self::Strategy {
+        unchecked_packet().boxed()
+    }
+}
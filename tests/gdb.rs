@@ -0,0 +1,83 @@
+//! Runs a real GDB against the `echo` example, to catch interoperability
+//! bugs the unit tests can't. This is ignored by default, run it with
+//! `cargo test --test gdb -- --ignored`. It is skipped if `gdb` isn't
+//! on the `PATH`.
+
+use std::{
+    env,
+    io::{prelude::*, BufReader},
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+/// The `echo` example, which `cargo test` builds next to the tests.
+fn echo_example() -> PathBuf {
+    let mut path = env::current_exe().unwrap();
+    path.pop(); // the test executable
+    if path.ends_with("deps") {
+        path.pop();
+    }
+    path.join("examples")
+        .join(format!("echo{}", env::consts::EXE_SUFFIX))
+}
+
+fn gdb_available() -> bool {
+    Command::new("gdb")
+        .arg("--version")
+        .stdout(Stdio::null())
+        .status()
+        .is_ok()
+}
+
+#[test]
+#[ignore]
+fn gdb_connects_to_echo() {
+    if !gdb_available() {
+        eprintln!("gdb is not on the PATH, skipping");
+        return;
+    }
+
+    let mut server = Command::new(echo_example())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("the echo example should have been built by cargo test");
+    let mut stdout = BufReader::new(server.stdout.take().unwrap());
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    let port: u16 = line
+        .trim()
+        .strip_prefix("Listening on port ")
+        .and_then(|port| port.parse().ok())
+        .unwrap_or_else(|| panic!("unexpected output from echo: {:?}", line));
+
+    let output = Command::new("gdb")
+        .args(["--batch", "-nx"])
+        .args(["-ex", "set remotetimeout 5"])
+        .args(["-ex", &format!("target remote 127.0.0.1:{}", port)])
+        .args(["-ex", "info threads"])
+        .args(["-ex", "detach"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    let mut log = String::new();
+    stdout.read_to_string(&mut log).unwrap();
+    assert!(server.wait().unwrap().success(), "{}", log);
+
+    for error in &[
+        "Remote communication error",
+        "Ignoring packet error",
+        "Bad checksum",
+        "Remote replied unexpectedly",
+        "Malformed",
+    ] {
+        assert!(
+            !stderr.contains(error),
+            "gdb reported {:?}:\n{}\nwhile the server logged:\n{}",
+            error,
+            stderr,
+            log
+        );
+    }
+    assert!(log.contains("-> D"), "{}", log);
+}
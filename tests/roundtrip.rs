@@ -0,0 +1,35 @@
+#![cfg(feature = "testing")]
+
+use gdb_protocol::{
+    packet::UncheckedPacket,
+    parser::{parse_all, Parser},
+    testing,
+};
+
+use proptest::prelude::*;
+
+proptest! {
+    #[test]
+    fn encoded_packets_parse_back(packet in any::<UncheckedPacket>()) {
+        let mut encoded = Vec::new();
+        packet.encode(&mut encoded).unwrap();
+        prop_assert_eq!(encoded.len(), packet.encoded_len());
+
+        let (read, parsed) = Parser::default().feed(&encoded).unwrap();
+        prop_assert_eq!(read, encoded.len());
+        prop_assert_eq!(parsed, Some(packet));
+    }
+
+    #[test]
+    fn checked_packets_stay_valid(packets in prop::collection::vec(testing::checked_packet(), 0..8)) {
+        let mut encoded = Vec::new();
+        for packet in &packets {
+            packet.encode(&mut encoded).unwrap();
+        }
+        let parsed = parse_all(&encoded).unwrap();
+        prop_assert_eq!(parsed.len(), packets.len());
+        for (parsed, packet) in parsed.into_iter().zip(&packets) {
+            prop_assert_eq!(&parsed.check().unwrap(), packet);
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use gdb_protocol::{
+    commands,
+    io::{GdbClient, GdbServer, BUF_SIZE},
+    packet::{CheckedPacket, Kind},
+};
+
+use std::{
+    net::{TcpListener, TcpStream},
+    thread,
+    time::Duration,
+};
+
+/// Find a free port for the server to listen on
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+#[test]
+fn client_connects_to_server() {
+    let port = free_port();
+
+    let server = thread::spawn(move || {
+        let mut server = GdbServer::listen(("127.0.0.1", port)).unwrap();
+        let packet = server.next_packet().unwrap().unwrap();
+        assert_eq!(packet.data, b"qC");
+        server.dispatch(&commands::ok_reply()).unwrap();
+        assert_eq!(server.next_packet().unwrap(), None);
+    });
+
+    let mut client = loop {
+        match GdbClient::connect(("127.0.0.1", port)) {
+            Ok(client) => break client,
+            Err(_) => thread::sleep(Duration::from_millis(10)),
+        }
+    };
+    client
+        .send(&CheckedPacket::from_data(Kind::Packet, b"qC".to_vec()))
+        .unwrap();
+    assert_eq!(client.next_packet().unwrap(), Some(commands::ok_reply()));
+    drop(client);
+
+    server.join().unwrap();
+}
+
+#[test]
+fn server_reads_through_buf_size() {
+    let port = free_port();
+
+    let server = thread::spawn(move || {
+        let server = GdbServer::listen(("127.0.0.1", port)).unwrap();
+        server.reader.capacity()
+    });
+
+    let _client = loop {
+        match TcpStream::connect(("127.0.0.1", port)) {
+            Ok(client) => break client,
+            Err(_) => thread::sleep(Duration::from_millis(10)),
+        }
+    };
+    assert_eq!(server.join().unwrap(), BUF_SIZE);
+}